@@ -9,7 +9,6 @@ use cudarc::driver::CudaContext;
 use libc::munmap;
 use nvidia_video_codec_sdk::{
     sys::nvEncodeAPI::{
-        NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ARGB,
         NV_ENC_CODEC_H264_GUID,
         NV_ENC_H264_PROFILE_HIGH_GUID,
         NV_ENC_PRESET_P1_GUID,
@@ -17,6 +16,7 @@ use nvidia_video_codec_sdk::{
     },
     Encoder,
     EncoderInitParams,
+    PixelFormat,
 };
 use vulkano::{
     device::{
@@ -158,7 +158,7 @@ fn main() {
     // Create a new CudaContext to interact with cuda.
     let cuda_ctx = CudaContext::new(0).expect("Cuda should be installed correctly.");
 
-    let encoder = Encoder::initialize_with_cuda(cuda_ctx.clone())
+    let encoder = Encoder::initialize_with_cuda(cuda_ctx)
         .expect("NVIDIA Video Codec SDK should be installed correctly.");
 
     // Get all encode guids supported by the GPU.
@@ -186,8 +186,8 @@ fn main() {
     let input_formats = encoder
         .get_supported_input_formats(encode_guid)
         .expect("The encoder should be able to get supported input buffer formats.");
-    let buffer_format = NV_ENC_BUFFER_FORMAT_ARGB;
-    assert!(input_formats.contains(&buffer_format));
+    let buffer_format = PixelFormat::Argb;
+    assert!(input_formats.contains(&buffer_format.into()));
 
     let tuning_info = NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_ULTRA_LOW_LATENCY;
 
@@ -253,26 +253,23 @@ fn main() {
         println!("Encoding frame {:>3} / {FRAMES}", i + 1);
         let output_bitstream = &mut output_buffers[i % num_bufs];
 
-        // Import file descriptor using CUDA.
-        let external_memory = unsafe {
-            cuda_ctx.import_external_memory(file_descriptor, (WIDTH * HEIGHT * 4) as u64)
+        // Import the file descriptor and register it with NVENC.
+        // The memory was written from the host, so no semaphores are needed.
+        let mut registered_resource = unsafe {
+            session.import_vulkan_memory(
+                file_descriptor,
+                u64::from(WIDTH * HEIGHT * 4),
+                buffer_format,
+                None,
+            )
         }
-        .expect("File descriptor should be valid for importing.");
-        let mapped_buffer = external_memory
-            .map_all()
-            .expect("External memory should be mappable.");
-
-        // Register and map with NVENC.
-        let mut registered_resource = session
-            .register_cuda_resource(WIDTH * 4, mapped_buffer)
-            .expect("Buffer should be mapped and available for registration with NVENC.");
+        .expect("File descriptor should be valid for importing and registration with NVENC.");
 
+        let mut mapped_resource = registered_resource
+            .map_rendered()
+            .expect("Registered resource should be mappable.");
         session
-            .encode_picture(
-                &mut registered_resource,
-                output_bitstream,
-                Default::default(),
-            )
+            .encode_picture(&mut mapped_resource, output_bitstream, Default::default())
             .expect("Encoder should be able to encode valid pictures");
 
         // Immediately locking is probably inefficient
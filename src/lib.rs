@@ -14,17 +14,26 @@
 //! The main entrypoint for the encoder API is the [`Encoder`] type.
 //!
 //! Usage follows this structure:
-//! 1. Initialize an [`Encoder`] with an encode device (such as CUDA).
+//! 1. Initialize an [`Encoder`] with an [`EncodeDevice`] (such as CUDA).
 //! 2. Configure the encoder and start a [`Session`].
-//! 3. Create input [`Buffer`]s  (or [`RegisteredResource`]) and output
-//!    [`Bitstream`]s.
+//! 3. Create input [`Buffer`]s  (or [`RegisteredResource`]s, which are mapped
+//!    for each frame) and output [`Bitstream`]s.
 //! 4. Encode frames with [`Session::encode_picture`].
 //!
 //! See the mentioned types for more info on how to use each.
 //!
 //! # Decoding
 //!
-//! There is no safe wrapper yet.
+//! See [NVIDIA Video Codec SDK - Video Decoder API Programming Guide](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvdec-video-decoder-api-prog-guide/index.html).
+//!
+//! The easiest way to decode an elementary stream is a [`DecodePipeline`],
+//! which returns the decoded frames in host memory.
+//!
+//! For more control, the pipeline is built from two parts:
+//! 1. A [`VideoParser`] splits the stream into pictures and calls back into a
+//!    [`ParserHandler`].
+//! 2. A [`Decoder`] decodes the pictures, which are then read with
+//!    [`Decoder::map_frame`].
 
 #![warn(
     missing_docs,
@@ -2,9 +2,21 @@
 
 use std::{ffi::c_void, ptr};
 
-use cudarc::driver::{DevicePtr, MappedBuffer};
+use cudarc::driver::{CudaSlice, DevicePtr, MappedBuffer};
 
-use super::{api::ENCODE_API, encoder::Encoder, result::EncodeError, session::Session};
+#[cfg(target_os = "linux")]
+use super::device::OpenGlDevice;
+use super::{
+    api::ENCODE_API,
+    device::{CudaDevice, EncodeDevice},
+    encoder::{EncoderRef, RawEncoder},
+    format::{PixelFormat, PITCH_ALIGNMENT},
+    frame::{Frame, PlanesMut},
+    result::{EncodeError, ErrorKind},
+    session::Session,
+};
+#[cfg(target_os = "linux")]
+use crate::sys::nvEncodeAPI::NV_ENC_INPUT_RESOURCE_OPENGL_TEX;
 use crate::sys::nvEncodeAPI::{
     NV_ENC_BUFFER_FORMAT,
     NV_ENC_CREATE_BITSTREAM_BUFFER,
@@ -40,15 +52,19 @@ pub trait EncoderOutput {
 }
 
 /// Functions for creating input and output buffers.
-impl Session {
+impl<D: EncodeDevice> Session<D> {
     /// Create a [`Buffer`].
     ///
     /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#creating-resources-required-to-hold-inputoutput-data).
     ///
     /// # Errors
     ///
-    /// Could error if the `width`, `height`, or `buffer_format` is invalid,
-    /// or if we run out of memory.
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// buffer format of the session is not a [`PixelFormat`].
+    ///
+    /// Could also error if the `width`, `height`, or `buffer_format` is
+    /// invalid, or if we run out of memory.
     ///
     /// # Examples
     ///
@@ -93,6 +109,15 @@ impl Session {
     ///     .unwrap();
     /// ```
     pub fn create_input_buffer(&self) -> Result<Buffer<'_>, EncodeError> {
+        self.create_input_buffer_with(EncoderRef::Borrowed(&self.encoder.raw))
+    }
+
+    /// Create a [`Buffer`] which holds the given reference to the encoder.
+    pub(crate) fn create_input_buffer_with<'a>(
+        &self,
+        encoder: EncoderRef<'a>,
+    ) -> Result<Buffer<'a>, EncodeError> {
+        let format = PixelFormat::try_from(self.buffer_format)?;
         let mut create_input_buffer_params = NV_ENC_CREATE_INPUT_BUFFER {
             version: NV_ENC_CREATE_INPUT_BUFFER_VER,
             width: self.width,
@@ -102,13 +127,16 @@ impl Session {
             ..Default::default()
         };
         unsafe {
-            (ENCODE_API.create_input_buffer)(self.encoder.ptr, &mut create_input_buffer_params)
+            (ENCODE_API.create_input_buffer)(self.encoder.raw.ptr, &mut create_input_buffer_params)
         }
         .result(&self.encoder)?;
         Ok(Buffer {
             ptr: create_input_buffer_params.inputBuffer,
             pitch: self.width,
-            encoder: &self.encoder,
+            width: self.width,
+            height: self.height,
+            format,
+            encoder,
         })
     }
 
@@ -163,6 +191,14 @@ impl Session {
     ///     .unwrap();
     /// ```
     pub fn create_output_bitstream(&self) -> Result<Bitstream<'_>, EncodeError> {
+        self.create_output_bitstream_with(EncoderRef::Borrowed(&self.encoder.raw))
+    }
+
+    /// Create a [`Bitstream`] which holds the given reference to the encoder.
+    pub(crate) fn create_output_bitstream_with<'a>(
+        &self,
+        encoder: EncoderRef<'a>,
+    ) -> Result<Bitstream<'a>, EncodeError> {
         let mut create_bitstream_buffer_params = NV_ENC_CREATE_BITSTREAM_BUFFER {
             version: NV_ENC_CREATE_BITSTREAM_BUFFER_VER,
             bitstreamBuffer: ptr::null_mut(),
@@ -170,57 +206,37 @@ impl Session {
         };
         unsafe {
             (ENCODE_API.create_bitstream_buffer)(
-                self.encoder.ptr,
+                self.encoder.raw.ptr,
                 &mut create_bitstream_buffer_params,
             )
         }
         .result(&self.encoder)?;
         Ok(Bitstream {
             ptr: create_bitstream_buffer_params.bitstreamBuffer,
-            encoder: &self.encoder,
+            encoder,
         })
     }
 
-    /// Create a [`RegisteredResource`] from a [`MappedBuffer`].
-    ///
-    /// See [`Session::register_generic_resource`].
-    ///
-    /// `pitch` should be set to the value obtained from `cuMemAllocPitch()`,
-    /// or to the width in **bytes** (if this resource was created by using
-    /// `cuMemAlloc()`). This value must be a multiple of 4.
-    ///
-    /// # Errors
-    ///
-    /// Could error if registration or mapping fails,
-    /// if the resource is invalid, or if we run out of memory.
-    pub fn register_cuda_resource(
-        &self,
-        pitch: u32,
-        mapped_buffer: MappedBuffer,
-    ) -> Result<RegisteredResource<'_, MappedBuffer>, EncodeError> {
-        let stream = self.encoder.ctx.default_stream();
-        let (device_ptr, _) = mapped_buffer.device_ptr(&stream);
-        self.register_generic_resource(
-            mapped_buffer,
-            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
-            device_ptr as *mut c_void,
-            pitch,
-        )
-    }
-
     /// Create a [`RegisteredResource`].
     ///
     /// This function is generic in the marker. This is so that you can
     /// optionally put a value on the [`RegisteredResource`] to make sure that
     /// value does not get dropped while the resource is registered. You should
     /// prefer using specific functions for the resource you are registering,
-    /// such as [`Session::register_cuda_resource`], when they are available.
+    /// such as [`Session::register_cuda_resource`],
+    /// [`Session::register_cuda_slice`], or [`Session::register_gl_texture`],
+    /// when they are available.
     ///
     /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#input-buffers-allocated-externally).
     ///
     /// # Errors
     ///
-    /// Could error if registration or mapping fails,
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// `pitch` is smaller than a row of the frame, see
+    /// [`PixelFormat::min_pitch`].
+    ///
+    /// Could also error if registration fails,
     /// if the resource is invalid, or if we run out of memory.
     pub fn register_generic_resource<T>(
         &self,
@@ -229,6 +245,34 @@ impl Session {
         resource_to_register: *mut c_void,
         pitch: u32,
     ) -> Result<RegisteredResource<'_, T>, EncodeError> {
+        self.register_generic_resource_with(
+            EncoderRef::Borrowed(&self.encoder.raw),
+            marker,
+            resource_type,
+            resource_to_register,
+            pitch,
+        )
+    }
+
+    /// Create a [`RegisteredResource`] which holds the given reference to the
+    /// encoder.
+    pub(crate) fn register_generic_resource_with<'a, T>(
+        &self,
+        encoder: EncoderRef<'a>,
+        marker: T,
+        resource_type: NV_ENC_INPUT_RESOURCE_TYPE,
+        resource_to_register: *mut c_void,
+        pitch: u32,
+    ) -> Result<RegisteredResource<'a, T>, EncodeError> {
+        if let Ok(format) = PixelFormat::try_from(self.buffer_format) {
+            let min_pitch = format.min_pitch(self.width);
+            if pitch < min_pitch {
+                return Err(EncodeError::new(
+                    ErrorKind::InvalidParam,
+                    format!("The pitch {pitch} is smaller than a row of {min_pitch} bytes."),
+                ));
+            }
+        }
         // Register resource.
         let mut register_resource_params = NV_ENC_REGISTER_RESOURCE::new(
             resource_type,
@@ -238,34 +282,213 @@ impl Session {
             self.buffer_format,
         )
         .pitch(pitch);
-        unsafe { (ENCODE_API.register_resource)(self.encoder.ptr, &mut register_resource_params) }
-            .result(&self.encoder)?;
-        let registered_resource = register_resource_params.registeredResource;
-
-        // Map resource.
-        let mut map_input_resource_params = NV_ENC_MAP_INPUT_RESOURCE {
-            version: NV_ENC_MAP_INPUT_RESOURCE_VER,
-            registeredResource: registered_resource,
-            mappedResource: ptr::null_mut(),
-            mappedBufferFmt: NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_UNDEFINED,
-            ..Default::default()
-        };
         unsafe {
-            (ENCODE_API.map_input_resource)(self.encoder.ptr, &mut map_input_resource_params)
+            (ENCODE_API.register_resource)(self.encoder.raw.ptr, &mut register_resource_params)
         }
         .result(&self.encoder)?;
 
-        let mapped_resource = map_input_resource_params.mappedResource;
         Ok(RegisteredResource {
-            reg_ptr: registered_resource,
-            map_ptr: mapped_resource,
+            reg_ptr: register_resource_params.registeredResource,
             pitch,
-            encoder: &self.encoder,
-            _marker: marker,
+            encoder,
+            marker,
         })
     }
 }
 
+/// Functions for registering CUDA device memory.
+impl Session<CudaDevice> {
+    /// Create a [`RegisteredResource`] from a [`MappedBuffer`].
+    ///
+    /// See [`Session::register_generic_resource`].
+    ///
+    /// `pitch` should be set to the value obtained from `cuMemAllocPitch()`,
+    /// or to the width in **bytes** (if this resource was created by using
+    /// `cuMemAlloc()`). This value must be a multiple of 4.
+    ///
+    /// # Errors
+    ///
+    /// Could error if registration fails,
+    /// if the resource is invalid, or if we run out of memory.
+    pub fn register_cuda_resource(
+        &self,
+        pitch: u32,
+        mapped_buffer: MappedBuffer,
+    ) -> Result<RegisteredResource<'_, MappedBuffer>, EncodeError> {
+        let stream = self.encoder.cuda_ctx().default_stream();
+        let (device_ptr, _) = mapped_buffer.device_ptr(&stream);
+        self.register_generic_resource(
+            mapped_buffer,
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
+            device_ptr as *mut c_void,
+            pitch,
+        )
+    }
+
+    /// Allocate device memory for a single input frame, with a pitched 2D
+    /// layout.
+    ///
+    /// Each row of the frame is padded to a multiple of 256 bytes, which keeps
+    /// rows aligned for CUDA kernels writing into the frame. The memory is
+    /// zeroed. Returns the allocation together with its pitch, which can be
+    /// passed straight to [`Session::register_cuda_slice`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// buffer format of the session is not a [`PixelFormat`].
+    ///
+    /// Could also error if we run out of memory.
+    pub fn alloc_pitched_slice(&self) -> Result<(CudaSlice<u8>, u32), EncodeError> {
+        const ROW_ALIGNMENT: u32 = 256;
+        let format = PixelFormat::try_from(self.buffer_format)?;
+        let pitch =
+            (format.min_pitch(self.width) + ROW_ALIGNMENT - 1) / ROW_ALIGNMENT * ROW_ALIGNMENT;
+        let size = format.frame_size(self.width, self.height, pitch);
+        let slice = self
+            .encoder
+            .cuda_ctx()
+            .default_stream()
+            .alloc_zeros::<u8>(size)?;
+        Ok((slice, pitch))
+    }
+
+    /// Create a [`RegisteredResource`] from a [`CudaSlice`].
+    ///
+    /// See [`Session::register_generic_resource`].
+    ///
+    /// The slice holds a single frame in the buffer format of the session,
+    /// where each row of the first plane is `pitch` bytes long. The slice is
+    /// borrowed for as long as the resource is registered. Device memory with
+    /// a suitable layout can be allocated using
+    /// [`Session::alloc_pitched_slice`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// buffer format of the session is not a [`PixelFormat`], if `pitch` is
+    /// not a multiple of 4 or is smaller than a row of the frame, or if the
+    /// slice is too small to hold the frame.
+    ///
+    /// Could also error if registration fails, or if we run out of memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     EncodePictureParams, Encoder, EncoderInitParams, PixelFormat,
+    /// # };
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, 1920, 1080);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// // Allocate device memory for a frame, which could be filled by a kernel.
+    /// let (slice, pitch) = session.alloc_pitched_slice().unwrap();
+    /// let mut input = session.register_cuda_slice(&slice, pitch).unwrap();
+    ///
+    /// let mut output_bitstream = session.create_output_bitstream().unwrap();
+    /// // The input has to stay mapped until the encode has completed.
+    /// let mut mapped = input.map().unwrap();
+    /// session
+    ///     .encode_picture(
+    ///         &mut mapped,
+    ///         &mut output_bitstream,
+    ///         EncodePictureParams::default(),
+    ///     )
+    ///     .unwrap();
+    /// let _data = output_bitstream.lock().unwrap().data();
+    /// drop(mapped);
+    /// ```
+    pub fn register_cuda_slice<'s>(
+        &'s self,
+        slice: &'s CudaSlice<u8>,
+        pitch: u32,
+    ) -> Result<RegisteredResource<'s, &'s CudaSlice<u8>>, EncodeError> {
+        let format = PixelFormat::try_from(self.buffer_format)?;
+        if pitch % PITCH_ALIGNMENT != 0 {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!("The pitch {pitch} is not a multiple of {PITCH_ALIGNMENT}."),
+            ));
+        }
+        let size = format.frame_size(self.width, self.height, pitch);
+        if slice.len() < size {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "A {}x{} frame in the {format:?} format with pitch {pitch} is {size} bytes, \
+                     but the slice is only {} bytes.",
+                    self.width,
+                    self.height,
+                    slice.len()
+                ),
+            ));
+        }
+        let stream = self.encoder.cuda_ctx().default_stream();
+        let (device_ptr, _) = slice.device_ptr(&stream);
+        self.register_generic_resource(
+            slice,
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
+            device_ptr as *mut c_void,
+            pitch,
+        )
+    }
+}
+
+/// Functions for registering OpenGL textures.
+#[cfg(target_os = "linux")]
+impl Session<OpenGlDevice> {
+    /// Create a [`RegisteredResource`] from an OpenGL texture.
+    ///
+    /// See [`Session::register_generic_resource`].
+    ///
+    /// `texture` is the name of the texture and `target` its texture target,
+    /// which must be either `GL_TEXTURE_2D` or `GL_TEXTURE_RECTANGLE`. The
+    /// texture holds a single frame in the buffer format of the session.
+    ///
+    /// The encoder has to be created using
+    /// [`Encoder::initialize_with_opengl`](super::Encoder::initialize_with_opengl),
+    /// and the texture must belong to the OpenGL context which was current at
+    /// that time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// buffer format of the session is not a [`PixelFormat`].
+    ///
+    /// Could also error if registration fails,
+    /// if the texture is invalid, or if we run out of memory.
+    pub fn register_gl_texture(
+        &self,
+        texture: u32,
+        target: u32,
+    ) -> Result<RegisteredResource<'_, Box<NV_ENC_INPUT_RESOURCE_OPENGL_TEX>>, EncodeError> {
+        let format = PixelFormat::try_from(self.buffer_format)?;
+        // The texture description is passed by pointer, so keep it alive for
+        // as long as the texture is registered.
+        let mut resource = Box::new(NV_ENC_INPUT_RESOURCE_OPENGL_TEX { texture, target });
+        let resource_ptr = ptr::addr_of_mut!(*resource).cast::<c_void>();
+        self.register_generic_resource(
+            resource,
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_OPENGL_TEX,
+            resource_ptr,
+            format.min_pitch(self.width),
+        )
+    }
+}
+
 /// Abstraction around input buffer allocated using
 /// the NVIDIA Video Encoder API.
 ///
@@ -274,7 +497,10 @@ impl Session {
 pub struct Buffer<'a> {
     pub(crate) ptr: *mut c_void,
     pitch: u32,
-    encoder: &'a Encoder,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    encoder: EncoderRef<'a>,
 }
 
 unsafe impl Send for Buffer<'_> {}
@@ -373,7 +599,7 @@ impl<'a> Buffer<'a> {
             lock_input_buffer_params.set_doNotWait(1);
         }
         unsafe { (ENCODE_API.lock_input_buffer)(self.encoder.ptr, &mut lock_input_buffer_params) }
-            .result(self.encoder)?;
+            .result_raw(&self.encoder)?;
 
         let data_ptr = lock_input_buffer_params.bufferDataPtr;
         let pitch = lock_input_buffer_params.pitch;
@@ -390,7 +616,7 @@ impl<'a> Buffer<'a> {
 impl Drop for Buffer<'_> {
     fn drop(&mut self) {
         unsafe { (ENCODE_API.destroy_input_buffer)(self.encoder.ptr, self.ptr) }
-            .result(self.encoder)
+            .result_raw(&self.encoder)
             .expect("The encoder and buffer pointers should be valid.");
     }
 }
@@ -415,13 +641,15 @@ impl EncoderInput for Buffer<'_> {
 pub struct BufferLock<'a, 'b> {
     buffer: &'a Buffer<'b>,
     data_ptr: *mut c_void,
-    #[allow(dead_code)]
     pitch: u32,
 }
 
 impl BufferLock<'_, '_> {
     /// Write data to the buffer.
     ///
+    /// Prefer [`BufferLock::write_frame`], which checks the size of the data
+    /// and accounts for the pitch of the buffer.
+    ///
     /// # Safety
     ///
     /// The size of the data should be less or equal to the size of the buffer.
@@ -430,18 +658,164 @@ impl BufferLock<'_, '_> {
     /// The user should also account for pitch, the data is written
     /// contiguously.
     pub unsafe fn write(&mut self, data: &[u8]) {
-        // TODO: Make this safe by doing checks.
-        // - Check that length of data fits (depends on format).
-        // - Write pitched?
         data.as_ptr()
             .copy_to(self.data_ptr.cast::<u8>(), data.len());
     }
+
+    /// Write a frame to the buffer.
+    ///
+    /// The frame is copied row by row, so that each row starts at the pitch
+    /// of the locked buffer, which may be larger than the pitch of the frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// width, height, or pixel format of the frame does not match the
+    /// session.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     Encoder, EncoderInitParams, Frame, PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// // An NV12 frame has a full resolution luma plane
+    /// // and an interleaved chroma plane of half the height.
+    /// let data = vec![0; (WIDTH * HEIGHT * 3 / 2) as usize];
+    /// let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    ///
+    /// let mut input_buffer = session.create_input_buffer().unwrap();
+    /// input_buffer.lock().unwrap().write_frame(&frame).unwrap();
+    /// ```
+    pub fn write_frame(&mut self, frame: &Frame<'_>) -> Result<(), EncodeError> {
+        let buffer = self.buffer;
+        if frame.format() != buffer.format
+            || frame.width() != buffer.width
+            || frame.height() != buffer.height
+        {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "Expected a {}x{} frame in the {:?} format, but got a {}x{} frame in the {:?} \
+                     format.",
+                    buffer.width,
+                    buffer.height,
+                    buffer.format,
+                    frame.width(),
+                    frame.height(),
+                    frame.format()
+                ),
+            ));
+        }
+
+        let data = frame.data();
+        let data_ptr = self.data_ptr.cast::<u8>();
+        let mut src_offset = 0;
+        let mut dst_offset = 0;
+        for plane in buffer.format.plane_layouts(buffer.width, buffer.height) {
+            let src_pitch = plane.pitch(frame.pitch() as usize);
+            let dst_pitch = plane.pitch(self.pitch as usize);
+            for row in 0..plane.rows {
+                let src = &data[src_offset + row * src_pitch..][..plane.row_bytes];
+                // SAFETY: The locked buffer holds each plane with the pitch
+                // returned by the driver, so the row is within the buffer.
+                unsafe {
+                    src.as_ptr().copy_to_nonoverlapping(
+                        data_ptr.add(dst_offset + row * dst_pitch),
+                        plane.row_bytes,
+                    );
+                }
+            }
+            src_offset += src_pitch * plane.rows;
+            dst_offset += dst_pitch * plane.rows;
+        }
+        Ok(())
+    }
+
+    /// Get mutable views of the planes of the buffer.
+    ///
+    /// This allows rendering or converting a frame straight into the input
+    /// buffer, without first assembling it in a separate [`Frame`]. The plane
+    /// sizes and pitches are derived from the pixel format of the session
+    /// and the pitch of the locked buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     Encoder, EncoderInitParams, PixelFormat, PlanesMut,
+    /// # };
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, 1920, 1080);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// let mut input_buffer = session.create_input_buffer().unwrap();
+    /// let mut lock = input_buffer.lock().unwrap();
+    /// let PlanesMut::SemiPlanar {
+    ///     mut luma,
+    ///     mut chroma,
+    /// } = lock.planes_mut()
+    /// else {
+    ///     panic!("NV12 is a semi-planar format");
+    /// };
+    /// // Draw a horizontal gradient in grey.
+    /// for row in luma.rows_mut() {
+    ///     for (x, pixel) in row.iter_mut().enumerate() {
+    ///         *pixel = (x % 256) as u8;
+    ///     }
+    /// }
+    /// for row in chroma.rows_mut() {
+    ///     row.fill(128);
+    /// }
+    /// ```
+    pub fn planes_mut(&mut self) -> PlanesMut<'_> {
+        let buffer = self.buffer;
+        let size = buffer
+            .format
+            .frame_size(buffer.width, buffer.height, self.pitch);
+        // SAFETY: The locked buffer holds all planes with the pitch returned
+        // by the driver, and the lock is borrowed mutably for the lifetime of
+        // the slice.
+        let data = unsafe { std::slice::from_raw_parts_mut(self.data_ptr.cast::<u8>(), size) };
+        PlanesMut::new(
+            buffer.format,
+            buffer.width,
+            buffer.height,
+            self.pitch as usize,
+            data,
+        )
+    }
 }
 
 impl Drop for BufferLock<'_, '_> {
     fn drop(&mut self) {
         unsafe { (ENCODE_API.unlock_input_buffer)(self.buffer.encoder.ptr, self.buffer.ptr) }
-            .result(self.buffer.encoder)
+            .result_raw(&self.buffer.encoder)
             .expect("The encoder and buffer pointers should be valid.");
     }
 }
@@ -453,7 +827,7 @@ impl Drop for BufferLock<'_, '_> {
 #[derive(Debug)]
 pub struct Bitstream<'a> {
     pub(crate) ptr: *mut c_void,
-    encoder: &'a Encoder,
+    encoder: EncoderRef<'a>,
 }
 
 unsafe impl Send for Bitstream<'_> {}
@@ -504,8 +878,10 @@ impl Bitstream<'_> {
         if !wait {
             lock_bitstream_buffer_params.set_doNotWait(1);
         }
+        // Needed for the block counts and motion vectors in the stats.
+        lock_bitstream_buffer_params.set_getRCStats(1);
         unsafe { (ENCODE_API.lock_bitstream)(self.encoder.ptr, &mut lock_bitstream_buffer_params) }
-            .result(self.encoder)?;
+            .result_raw(&self.encoder)?;
 
         // Get data.
         let data_ptr = lock_bitstream_buffer_params.bitstreamBufferPtr;
@@ -519,6 +895,19 @@ impl Bitstream<'_> {
             timestamp: lock_bitstream_buffer_params.outputTimeStamp,
             duration: lock_bitstream_buffer_params.outputDuration,
             picture_type: lock_bitstream_buffer_params.pictureType,
+            alpha_layer_size: lock_bitstream_buffer_params.alphaLayerSizeInBytes as usize,
+            temporal_id: lock_bitstream_buffer_params.temporalId,
+            stats: EncodeStats {
+                picture_type: lock_bitstream_buffer_params.pictureType,
+                average_qp: lock_bitstream_buffer_params.frameAvgQP,
+                satd: lock_bitstream_buffer_params.frameSatd,
+                intra_block_count: lock_bitstream_buffer_params.intraMBCount,
+                inter_block_count: lock_bitstream_buffer_params.interMBCount,
+                average_motion_vector: (
+                    lock_bitstream_buffer_params.averageMVX,
+                    lock_bitstream_buffer_params.averageMVY,
+                ),
+            },
         })
     }
 }
@@ -526,7 +915,7 @@ impl Bitstream<'_> {
 impl Drop for Bitstream<'_> {
     fn drop(&mut self) {
         unsafe { (ENCODE_API.destroy_bitstream_buffer)(self.encoder.ptr, self.ptr) }
-            .result(self.encoder)
+            .result_raw(&self.encoder)
             .expect("The encoder and bitstream pointers should be valid.");
     }
 }
@@ -537,6 +926,26 @@ impl EncoderOutput for Bitstream<'_> {
     }
 }
 
+/// Statistics of an encoded frame, reported by the encoder.
+///
+/// This type is returned by [`BitstreamLock::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeStats {
+    /// The picture type of the encoded frame.
+    pub picture_type: NV_ENC_PIC_TYPE,
+    /// The average QP of the frame.
+    pub average_qp: u32,
+    /// The total SATD cost of the frame.
+    pub satd: u32,
+    /// The number of intra coded blocks, which are macroblocks for H.264,
+    /// CTBs for HEVC, and superblocks for AV1.
+    pub intra_block_count: u32,
+    /// The number of inter coded blocks, including skipped blocks.
+    pub inter_block_count: u32,
+    /// The average motion vector of the frame, in the X and Y direction.
+    pub average_motion_vector: (i32, i32),
+}
+
 /// An RAII lock on the output bitstream buffer.
 ///
 /// This type is created via [`Bitstream::lock`] or [`Bitstream::try_lock`].
@@ -551,6 +960,9 @@ pub struct BitstreamLock<'a, 'b> {
     timestamp: u64,
     duration: u64,
     picture_type: NV_ENC_PIC_TYPE,
+    alpha_layer_size: usize,
+    temporal_id: u32,
+    stats: EncodeStats,
     // TODO: other fields
 }
 
@@ -584,52 +996,161 @@ impl BitstreamLock<'_, '_> {
     pub fn picture_type(&self) -> NV_ENC_PIC_TYPE {
         self.picture_type
     }
+
+    /// Getter for the temporal ID of the frame.
+    ///
+    /// This is always 0 unless temporal layers are enabled, see
+    /// [`TemporalLayers`](crate::TemporalLayers).
+    #[must_use]
+    pub fn temporal_id(&self) -> u32 {
+        self.temporal_id
+    }
+
+    /// Getter for the statistics of the encoded frame.
+    #[must_use]
+    pub fn stats(&self) -> EncodeStats {
+        self.stats
+    }
+
+    /// Getter for the data of the base layer.
+    ///
+    /// This is the same as [`BitstreamLock::data`] unless HEVC alpha layer
+    /// encoding is enabled, in which case the alpha layer is excluded.
+    #[must_use]
+    pub fn base_layer_data(&self) -> &[u8] {
+        &self.data[..self.data.len().saturating_sub(self.alpha_layer_size)]
+    }
+
+    /// Getter for the data of the alpha layer.
+    ///
+    /// This is empty unless HEVC alpha layer encoding is enabled.
+    /// The alpha layer follows the base layer in [`BitstreamLock::data`].
+    #[must_use]
+    pub fn alpha_layer_data(&self) -> &[u8] {
+        &self.data[self.data.len().saturating_sub(self.alpha_layer_size)..]
+    }
 }
 
 impl Drop for BitstreamLock<'_, '_> {
     fn drop(&mut self) {
         unsafe { (ENCODE_API.unlock_bitstream)(self.bitstream.encoder.ptr, self.bitstream.ptr) }
-            .result(self.bitstream.encoder)
+            .result_raw(&self.bitstream.encoder)
             .expect("The encoder and bitstream pointers should be valid.");
     }
 }
 
-/// Abstraction for a registered and mapped external resource.
+/// Abstraction for a registered external resource.
 ///
 /// The Encoder API exposes a way to use input buffers allocated externally,
-/// for example through CUDA or OpenGL.
+/// for example through CUDA or OpenGL. The resource is registered once, and
+/// then mapped using [`RegisteredResource::map`] for each frame it is used
+/// as the input of. While the resource is not mapped, the client is free to
+/// access it through other APIs, for example to render the next frame into
+/// it. This allows reusing a ring of registered resources.
 ///
-/// The buffer is automatically unmapped and unregistered when dropped.
+/// The buffer is automatically unregistered when dropped.
 /// The external buffer memory should still be properly destroyed by the client.
 #[derive(Debug)]
 pub struct RegisteredResource<'a, T> {
     pub(crate) reg_ptr: *mut c_void,
-    pub(crate) map_ptr: *mut c_void,
     pitch: u32,
-    encoder: &'a Encoder,
+    encoder: EncoderRef<'a>,
     // A generic marker to make sure the external resources are dropped
     // after the resource is unregistered.
-    _marker: T,
+    marker: T,
 }
 
 unsafe impl Send for RegisteredResource<'_, MappedBuffer> {}
+unsafe impl Send for RegisteredResource<'_, &CudaSlice<u8>> {}
+
+impl<T> RegisteredResource<'_, T> {
+    /// Map the resource so that it can be used as an encoder input.
+    ///
+    /// On drop, [`MappedResource`] will unmap the resource. The resource
+    /// should stay mapped until the encode it was used for has completed,
+    /// which is the case once the output bitstream of that encode is locked.
+    ///
+    /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#input-buffers-allocated-externally).
+    ///
+    /// # Errors
+    ///
+    /// Could error if mapping fails or if we run out of memory.
+    pub fn map(&mut self) -> Result<MappedResource<'_>, EncodeError> {
+        self.map_with_marker().map(|(mapped, _)| mapped)
+    }
+
+    /// Getter for the marker.
+    #[cfg(all(feature = "vulkan", unix))]
+    pub(crate) fn marker(&self) -> &T {
+        &self.marker
+    }
+
+    /// Same as [`RegisteredResource::map`], but also returns the marker.
+    pub(crate) fn map_with_marker(&mut self) -> Result<(MappedResource<'_>, &T), EncodeError> {
+        let mut map_input_resource_params = NV_ENC_MAP_INPUT_RESOURCE {
+            version: NV_ENC_MAP_INPUT_RESOURCE_VER,
+            registeredResource: self.reg_ptr,
+            mappedResource: ptr::null_mut(),
+            mappedBufferFmt: NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_UNDEFINED,
+            ..Default::default()
+        };
+        unsafe {
+            (ENCODE_API.map_input_resource)(self.encoder.ptr, &mut map_input_resource_params)
+        }
+        .result_raw(&self.encoder)?;
+        let mapped = MappedResource {
+            map_ptr: map_input_resource_params.mappedResource,
+            pitch: self.pitch,
+            buffer_format: map_input_resource_params.mappedBufferFmt,
+            encoder: &self.encoder,
+        };
+        Ok((mapped, &self.marker))
+    }
+}
 
-/// Automatically unmap and unregister the external resource
+/// Automatically unregister the external resource
 /// when it goes out of scope.
 impl<T> Drop for RegisteredResource<'_, T> {
     fn drop(&mut self) {
-        // Unmapping resource.
-        unsafe { (ENCODE_API.unmap_input_resource)(self.encoder.ptr, self.map_ptr) }
-            .result(self.encoder)
-            .expect("The encoder pointer and map handle should be valid.");
-        // Unregister resource.
         unsafe { (ENCODE_API.unregister_resource)(self.encoder.ptr, self.reg_ptr) }
-            .result(self.encoder)
+            .result_raw(&self.encoder)
             .expect("The encoder pointer and resource handle should be valid.");
     }
 }
 
-impl<T> EncoderInput for RegisteredResource<'_, T> {
+/// An RAII mapping of a [`RegisteredResource`].
+///
+/// This type is created via [`RegisteredResource::map`].
+/// It can be passed as the input of an encode, and automatically unmaps the
+/// resource when it goes out of scope.
+#[derive(Debug)]
+pub struct MappedResource<'a> {
+    map_ptr: *mut c_void,
+    pitch: u32,
+    buffer_format: NV_ENC_BUFFER_FORMAT,
+    encoder: &'a RawEncoder,
+}
+
+unsafe impl Send for MappedResource<'_> {}
+
+impl MappedResource<'_> {
+    /// Getter for the buffer format of the mapped resource.
+    #[must_use]
+    pub fn buffer_format(&self) -> NV_ENC_BUFFER_FORMAT {
+        self.buffer_format
+    }
+}
+
+/// Automatically unmap the resource when it goes out of scope.
+impl Drop for MappedResource<'_> {
+    fn drop(&mut self) {
+        unsafe { (ENCODE_API.unmap_input_resource)(self.encoder.ptr, self.map_ptr) }
+            .result_raw(self.encoder)
+            .expect("The encoder pointer and map handle should be valid.");
+    }
+}
+
+impl EncoderInput for MappedResource<'_> {
     fn pitch(&self) -> u32 {
         self.pitch
     }
@@ -10,7 +10,11 @@ use std::ffi::c_void;
 use crate::sys::nvEncodeAPI::{
     GUID,
     NV_ENC_BUFFER_FORMAT,
+    NV_ENC_BUFFER_USAGE,
     NV_ENC_CONFIG,
+    NV_ENC_CONFIG_AV1,
+    NV_ENC_CONFIG_H264,
+    NV_ENC_CONFIG_HEVC,
     NV_ENC_INITIALIZE_PARAMS,
     NV_ENC_INITIALIZE_PARAMS_VER,
     NV_ENC_INPUT_RESOURCE_TYPE,
@@ -83,6 +87,172 @@ impl NV_ENC_INITIALIZE_PARAMS {
     // TODO: Add other options
 }
 
+/// Configuration of temporal layers for hierarchical coding and temporal SVC.
+///
+/// Each encoded frame belongs to one temporal layer, which can be read with
+/// [`BitstreamLock::temporal_id`](crate::BitstreamLock::temporal_id). Frames
+/// in a layer only reference frames in the same or lower layers, so the
+/// higher layers can be dropped to reduce the framerate without breaking
+/// decoding of the remaining frames.
+///
+/// Use with [`NV_ENC_CONFIG_H264::temporal_svc`] or
+/// [`NV_ENC_CONFIG_HEVC::temporal_layers`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct TemporalLayers {
+    /// Number of temporal layers to encode. Must be in the range from 1 to
+    /// the value of
+    /// [`NV_ENC_CAPS::NV_ENC_CAPS_NUM_MAX_TEMPORAL_LAYERS`](crate::sys::nvEncodeAPI::NV_ENC_CAPS::NV_ENC_CAPS_NUM_MAX_TEMPORAL_LAYERS).
+    pub num_layers: u32,
+    /// Maximum number of temporal layers in the stream (H.264 only).
+    /// Must be greater or equal to `num_layers`.
+    pub max_layers: u32,
+    /// Write an SVC prefix NAL unit before each slice (H.264 only).
+    pub svc_prefix_nalu: bool,
+    /// Write a scalability information SEI message before each IDR picture
+    /// (H.264 only).
+    pub scalability_info_sei: bool,
+}
+
+impl TemporalLayers {
+    /// Create a configuration with `num_layers` temporal layers, using the
+    /// same number of maximum layers and writing SVC prefix NAL units.
+    #[must_use]
+    pub fn new(num_layers: u32) -> Self {
+        Self {
+            num_layers,
+            max_layers: num_layers,
+            svc_prefix_nalu: true,
+            scalability_info_sei: false,
+        }
+    }
+}
+
+/// Configuration of intra refresh, also known as gradual decoder refresh.
+///
+/// Instead of encoding a full intra frame, the intra coded blocks are spread
+/// over several frames. This avoids the bitrate spikes of IDR frames, which
+/// makes it useful for low latency streaming over lossy networks.
+///
+/// Intra refresh is ignored if the GOP structure uses B frames. Support for it
+/// can be checked with
+/// [`NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_INTRA_REFRESH`](crate::sys::nvEncodeAPI::NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_INTRA_REFRESH).
+///
+/// Use with [`NV_ENC_CONFIG_H264::intra_refresh`],
+/// [`NV_ENC_CONFIG_HEVC::intra_refresh`] or
+/// [`NV_ENC_CONFIG_AV1::intra_refresh`]. An intra refresh can then also be
+/// requested at any time using
+/// [`Session::request_intra_refresh`](crate::Session::request_intra_refresh).
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct IntraRefresh {
+    /// Number of frames between the start of two periodic intra refreshes,
+    /// or 0 to only refresh on demand. Periodic intra refresh requires
+    /// `gopLength` to be set to
+    /// [`NVENC_INFINITE_GOPLENGTH`](crate::sys::nvEncodeAPI::NVENC_INFINITE_GOPLENGTH).
+    pub period: u32,
+    /// Number of frames over which each periodic intra refresh is spread.
+    /// Should be smaller than `period`.
+    pub count: u32,
+    /// Keep a single slice per frame during intra refresh (H.264 and HEVC
+    /// only). Support for this can be checked with
+    /// [`NV_ENC_CAPS::NV_ENC_CAPS_SINGLE_SLICE_INTRA_REFRESH`](crate::sys::nvEncodeAPI::NV_ENC_CAPS::NV_ENC_CAPS_SINGLE_SLICE_INTRA_REFRESH).
+    pub single_slice: bool,
+}
+
+impl IntraRefresh {
+    /// Create a configuration which starts an intra refresh every `period`
+    /// frames, each spread over `count` frames.
+    #[must_use]
+    pub fn periodic(period: u32, count: u32) -> Self {
+        Self {
+            period,
+            count,
+            single_slice: false,
+        }
+    }
+
+    /// Create a configuration which only refreshes when requested with
+    /// [`Session::request_intra_refresh`](crate::Session::request_intra_refresh).
+    #[must_use]
+    pub fn on_demand() -> Self {
+        Self::periodic(0, 0)
+    }
+}
+
+impl NV_ENC_CONFIG_H264 {
+    /// Enable temporal SVC with the given temporal layers.
+    ///
+    /// Support for this can be checked with
+    /// [`NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_TEMPORAL_SVC`](crate::sys::nvEncodeAPI::NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_TEMPORAL_SVC).
+    /// This also raises `maxNumRefFrames` to the minimum required for the
+    /// number of layers.
+    pub fn temporal_svc(&mut self, layers: TemporalLayers) -> &mut Self {
+        self.set_enableTemporalSVC(1);
+        self.numTemporalLayers = layers.num_layers;
+        self.maxTemporalLayers = layers.max_layers;
+        self.set_disableSVCPrefixNalu(u32::from(!layers.svc_prefix_nalu));
+        self.set_enableScalabilityInfoSEI(u32::from(layers.scalability_info_sei));
+        // The reference frames must be able to hold all temporal layers.
+        self.maxNumRefFrames = self
+            .maxNumRefFrames
+            .max(layers.max_layers.saturating_sub(2) * 2);
+        self
+    }
+
+    /// Enable intra refresh with the given configuration.
+    pub fn intra_refresh(&mut self, refresh: IntraRefresh) -> &mut Self {
+        self.set_enableIntraRefresh(1);
+        self.intraRefreshPeriod = refresh.period;
+        self.intraRefreshCnt = refresh.count;
+        self.set_singleSliceIntraRefresh(u32::from(refresh.single_slice));
+        self
+    }
+}
+
+impl NV_ENC_CONFIG_HEVC {
+    /// Enable hierarchical coding with the given temporal layers.
+    ///
+    /// Only [`TemporalLayers::num_layers`] is used for HEVC, the temporal ID
+    /// is signalled in the NAL unit header of each frame.
+    pub fn temporal_layers(&mut self, layers: TemporalLayers) -> &mut Self {
+        self.maxTemporalLayersMinus1 = layers.num_layers.saturating_sub(1);
+        self
+    }
+
+    /// Enable HEVC encoding with an alpha layer.
+    ///
+    /// The alpha plane is then passed to each encode using
+    /// [`EncodePictureParams::alpha_input`](crate::EncodePictureParams::alpha_input).
+    /// Support for this can be checked with
+    /// [`NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_ALPHA_LAYER_ENCODING`](crate::sys::nvEncodeAPI::NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_ALPHA_LAYER_ENCODING).
+    /// The split of the bitrate between the base and alpha layer is set by
+    /// `alphaLayerBitrateRatio` in the rate control params.
+    pub fn enable_alpha_layer_encoding(&mut self) -> &mut Self {
+        self.set_enableAlphaLayerEncoding(1);
+        self
+    }
+
+    /// Enable intra refresh with the given configuration.
+    pub fn intra_refresh(&mut self, refresh: IntraRefresh) -> &mut Self {
+        self.set_enableIntraRefresh(1);
+        self.intraRefreshPeriod = refresh.period;
+        self.intraRefreshCnt = refresh.count;
+        self.set_singleSliceIntraRefresh(u32::from(refresh.single_slice));
+        self
+    }
+}
+
+impl NV_ENC_CONFIG_AV1 {
+    /// Enable intra refresh with the given configuration.
+    ///
+    /// [`IntraRefresh::single_slice`] is ignored for AV1.
+    pub fn intra_refresh(&mut self, refresh: IntraRefresh) -> &mut Self {
+        self.set_enableIntraRefresh(1);
+        self.intraRefreshPeriod = refresh.period;
+        self.intraRefreshCnt = refresh.count;
+        self
+    }
+}
+
 impl NV_ENC_PIC_PARAMS {
     /// Create an EOS empty frame that is used at the
     /// end of encoding to flush the encoder.
@@ -152,5 +322,16 @@ impl NV_ENC_REGISTER_RESOURCE {
         self
     }
 
+    /// Set the usage of the registered resource.
+    ///
+    /// Defaults to [`NV_ENC_BUFFER_USAGE::NV_ENC_INPUT_IMAGE`]. Set this to
+    /// [`NV_ENC_BUFFER_USAGE::NV_ENC_OUTPUT_RECON`] for buffers which receive
+    /// the reconstructed frame.
+    #[must_use]
+    pub fn buffer_usage(mut self, buffer_usage: NV_ENC_BUFFER_USAGE) -> Self {
+        self.bufferUsage = buffer_usage;
+        self
+    }
+
     // TODO: Add other options
 }
@@ -0,0 +1,515 @@
+//! Defines [`DecodePipeline`] which turns an elementary stream into decoded
+//! frames.
+//!
+//! The pipeline takes care of the details of driving the parser and the
+//! decoder: it creates the decoder from the first sequence header with as
+//! many decode surfaces as the stream needs, reconfigures or recreates it when
+//! the resolution changes, and copies the decoded frames into host memory in
+//! display order.
+
+use std::{collections::HashMap, mem, sync::Arc};
+
+use cudarc::driver::CudaContext;
+
+use super::{
+    decoder::{DecodeStatus, Decoder, DecoderInitParams},
+    format::PixelFormat,
+    frame::Frame,
+    parser::{
+        Av1OperatingPoint,
+        Av1OperatingPointChoice,
+        DisplayInfo,
+        ParserHandler,
+        ParserInitParams,
+        VideoFormat,
+        VideoParser,
+    },
+    result::{EncodeError, ErrorKind},
+    sei::SeiMessage,
+};
+use crate::sys::cuviddec::{
+    cudaVideoChromaFormat,
+    cudaVideoCodec,
+    cudaVideoDeinterlaceMode,
+    CUVIDPICPARAMS,
+};
+
+/// A decoded frame in host memory, returned by a [`DecodePipeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedFrame {
+    /// The planes of the frame, laid out as described by [`Frame`].
+    pub data: Vec<u8>,
+    /// The width of the frame in pixels.
+    pub width: u32,
+    /// The height of the frame in pixels.
+    pub height: u32,
+    /// The pitch of the luma plane in bytes.
+    pub pitch: u32,
+    /// The pixel format of the frame.
+    pub format: PixelFormat,
+    /// The presentation timestamp passed in with the data of the frame.
+    pub pts: i64,
+    /// Whether the frame was decoded without errors, or `None` if the GPU
+    /// does not report it for the codec. See [`Decoder::status`].
+    pub decode_status: Option<DecodeStatus>,
+    /// Whether the frame was coded as a progressive frame rather than as two
+    /// fields.
+    pub progressive: bool,
+    /// Whether the top field of the coded frame is displayed first.
+    pub top_field_first: bool,
+    /// The number of additional fields to display the first field for, or
+    /// -1 for an unpaired field.
+    pub repeat_first_field: i32,
+    /// Whether this frame is the second field of a deinterlaced frame.
+    pub second_field: bool,
+    /// The SEI messages of the picture, or its metadata OBUs for AV1. They
+    /// are only attached to the first field of a deinterlaced frame.
+    pub sei_messages: Vec<SeiMessage>,
+}
+
+impl DecodedFrame {
+    /// Borrow the frame as a [`Frame`], for example to encode it again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// fields were changed so that the data no longer matches the size of the
+    /// frame.
+    pub fn as_frame(&self) -> Result<Frame<'_>, EncodeError> {
+        Frame::with_pitch(self.format, self.width, self.height, self.pitch, &self.data)
+    }
+}
+
+/// The [`ParserHandler`] of a [`DecodePipeline`].
+#[derive(Debug)]
+struct PipelineHandler {
+    ctx: Arc<CudaContext>,
+    decoder: Option<Decoder>,
+    // The deinterlace mode for interlaced sequences.
+    deinterlace_mode: cudaVideoDeinterlaceMode,
+    // The operating point of AV1 streams.
+    av1_operating_point: Av1OperatingPointChoice,
+    // The unit of the timestamps in Hz.
+    clock_rate: u32,
+    // The format of the current sequence.
+    format: Option<VideoFormat>,
+    // The timestamp of the previous displayed picture.
+    last_pts: Option<i64>,
+    // The last positive difference between the timestamps of consecutive
+    // pictures.
+    frame_duration: Option<i64>,
+    // The second field of the previous picture, held back until the
+    // timestamp of the next picture is known. Its `pts` is still the one of
+    // the first field.
+    pending_field: Option<DecodedFrame>,
+    // Frames which have been displayed but not returned yet.
+    frames: Vec<DecodedFrame>,
+    // The SEI messages of the pictures which have not been displayed yet, by
+    // picture index.
+    sei_messages: HashMap<i32, Vec<SeiMessage>>,
+    // The first error raised inside a callback.
+    error: Option<EncodeError>,
+}
+
+impl PipelineHandler {
+    /// Create, reconfigure or keep the decoder for a new sequence header.
+    fn configure(&mut self, format: &VideoFormat) -> Result<u32, EncodeError> {
+        let surfaces = format.min_num_decode_surfaces;
+        let (width, height) = (format.display_area.width(), format.display_area.height());
+        let deinterlace_mode = if format.progressive {
+            cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Weave
+        } else {
+            self.deinterlace_mode
+        };
+        if let Some(decoder) = &mut self.decoder {
+            if self.format.as_ref() == Some(format) {
+                return Ok(decoder.num_decode_surfaces());
+            }
+            // The decoder only needs to be recreated if it does not have
+            // enough surfaces, or if the codec, chroma format, bit depth or
+            // deinterlace mode change. It recreates itself if the coded size
+            // grows.
+            let compatible = decoder.codec() == format.codec
+                && decoder.chroma_format() == format.chroma_format
+                && decoder.bit_depth() == format.bit_depth
+                && decoder.deinterlace_mode() == deinterlace_mode
+                && decoder.num_decode_surfaces() >= surfaces;
+            if compatible {
+                decoder.reconfigure(
+                    format.coded_width,
+                    format.coded_height,
+                    format.display_area,
+                    (width, height),
+                )?;
+                self.format = Some(*format);
+                return Ok(decoder.num_decode_surfaces());
+            }
+        }
+
+        // Destroy the old decoder before creating the new one, so they do
+        // not need memory at the same time.
+        self.decoder = None;
+        self.format = None;
+        let mut params =
+            DecoderInitParams::new(format.codec, format.coded_width, format.coded_height);
+        params
+            .chroma_format(format.chroma_format)
+            .bit_depth(format.bit_depth)
+            .num_decode_surfaces(surfaces)
+            .output_format(self.output_format(format)?)
+            .deinterlace_mode(deinterlace_mode)
+            .crop(format.display_area);
+        self.decoder = Some(Decoder::new(Arc::clone(&self.ctx), &params)?);
+        self.format = Some(*format);
+        Ok(surfaces)
+    }
+
+    /// Choose the output format which keeps the chroma format and bit depth
+    /// of the stream, falling back to any format the decoder supports.
+    fn output_format(&self, format: &VideoFormat) -> Result<PixelFormat, EncodeError> {
+        let preferred = match (format.chroma_format, format.bit_depth > 8) {
+            (cudaVideoChromaFormat::cudaVideoChromaFormat_444, false) => PixelFormat::Yuv444,
+            (cudaVideoChromaFormat::cudaVideoChromaFormat_444, true) => PixelFormat::Yuv444_16Bit,
+            (_, false) => PixelFormat::Nv12,
+            (_, true) => PixelFormat::P016,
+        };
+        let caps = Decoder::get_caps(
+            &self.ctx,
+            format.codec,
+            format.chroma_format,
+            format.bit_depth,
+        )?;
+        if caps.output_formats.contains(&preferred) {
+            Ok(preferred)
+        } else {
+            // Let the decoder report an unsupported stream.
+            Ok(caps.output_formats.first().copied().unwrap_or(preferred))
+        }
+    }
+
+    /// Copy a displayed picture into host memory, as two frames if it is
+    /// deinterlaced.
+    fn output(&mut self, info: &DisplayInfo) -> Result<(), EncodeError> {
+        if let Some(last_pts) = self.last_pts {
+            let duration = info.timestamp.saturating_sub(last_pts);
+            if duration > 0 {
+                self.frame_duration = Some(duration);
+            }
+        }
+        self.last_pts = Some(info.timestamp);
+        // The second field of the previous picture is halfway to this one.
+        if let Some(mut field) = self.pending_field.take() {
+            let duration = info.timestamp.saturating_sub(field.pts) / 2;
+            let duration = if duration > 0 {
+                duration
+            } else {
+                self.field_duration()
+            };
+            field.pts = field.pts.saturating_add(duration);
+            self.frames.push(field);
+        }
+        let mut sei_messages = self.sei_messages.remove(&info.picture_index);
+        let decoder = self.decoder()?;
+        let mut frames = Vec::with_capacity(2);
+        for second_field in [false, true]
+            .into_iter()
+            .take(decoder.num_output_frames(info) as usize)
+        {
+            let mapped = decoder.map_field(info, second_field)?;
+            // Mapping waits for the picture to be decoded, so the status is
+            // final.
+            let decode_status = match decoder.status(info.picture_index) {
+                Ok(status) => Some(status),
+                Err(err) if err.kind() == ErrorKind::UnsupportedParam => None,
+                Err(err) => return Err(err),
+            };
+            frames.push(DecodedFrame {
+                data: mapped.copy_to_host()?,
+                width: mapped.width(),
+                height: mapped.height(),
+                pitch: mapped.format().min_pitch(mapped.width()),
+                format: mapped.format(),
+                pts: info.timestamp,
+                decode_status,
+                progressive: info.progressive_frame,
+                top_field_first: info.top_field_first,
+                repeat_first_field: info.repeat_first_field,
+                second_field,
+                sei_messages: if second_field {
+                    Vec::new()
+                } else {
+                    sei_messages.take().unwrap_or_default()
+                },
+            });
+        }
+        if frames.len() == 2 {
+            self.pending_field = frames.pop();
+        }
+        self.frames.extend(frames);
+        Ok(())
+    }
+
+    /// Output the held back second field at the end of the stream.
+    fn finish_field(&mut self) {
+        if let Some(mut field) = self.pending_field.take() {
+            field.pts = field.pts.saturating_add(self.field_duration());
+            self.frames.push(field);
+        }
+    }
+
+    /// Estimate the time between the two fields of a picture when the
+    /// timestamp of the next picture is not known: half the time between the
+    /// previous pictures, or else half a frame at the frame rate of the
+    /// sequence in units of the clock rate.
+    fn field_duration(&self) -> i64 {
+        if let Some(frame_duration) = self.frame_duration {
+            return frame_duration / 2;
+        }
+        match self.format.map(|format| format.frame_rate) {
+            Some((numerator, denominator)) if numerator > 0 && denominator > 0 => {
+                i64::from(self.clock_rate) * i64::from(denominator) / i64::from(numerator) / 2
+            }
+            _ => 0,
+        }
+    }
+
+    fn decoder(&self) -> Result<&Decoder, EncodeError> {
+        self.decoder.as_ref().ok_or_else(|| {
+            EncodeError::new(
+                ErrorKind::InvalidCall,
+                "The stream did not start with a sequence header.",
+            )
+        })
+    }
+
+    /// Keep the first error of a callback, so it can be returned from the
+    /// pipeline.
+    fn fail(&mut self, err: EncodeError) {
+        self.error.get_or_insert(err);
+    }
+}
+
+impl ParserHandler for PipelineHandler {
+    fn sequence(&mut self, format: &VideoFormat) -> Option<u32> {
+        self.configure(format).map_err(|err| self.fail(err)).ok()
+    }
+
+    fn decode_picture(&mut self, picture: &mut CUVIDPICPARAMS) -> bool {
+        // SAFETY: The picture parameters come straight from the parser.
+        let result = self
+            .decoder()
+            .and_then(|decoder| unsafe { decoder.decode_picture(picture) });
+        result.map_err(|err| self.fail(err)).is_ok()
+    }
+
+    fn display_picture(&mut self, info: &DisplayInfo) -> bool {
+        self.output(info).map_err(|err| self.fail(err)).is_ok()
+    }
+
+    fn av1_operating_point(
+        &mut self,
+        operating_points: &[Av1OperatingPoint],
+    ) -> Option<Av1OperatingPointChoice> {
+        // Like the NVIDIA samples, fall back to the first operating point if
+        // the stream does not have the chosen one.
+        let mut choice = self.av1_operating_point;
+        if choice.index as usize >= operating_points.len() {
+            choice.index = 0;
+        }
+        Some(choice)
+    }
+
+    fn sei_messages(&mut self, picture_index: i32, messages: Vec<SeiMessage>) -> bool {
+        // Replaces the messages of a picture which was never displayed, as
+        // its surface is reused.
+        self.sei_messages.insert(picture_index, messages);
+        true
+    }
+}
+
+/// Decodes an elementary stream into frames in host memory.
+///
+/// The stream is passed in chunks of any size to [`DecodePipeline::decode`],
+/// which returns the frames which are ready in display order. H.264 and HEVC
+/// streams are expected in Annex B format, AV1 streams as a sequence of OBUs.
+/// At the end of the stream [`DecodePipeline::finish`] returns the remaining
+/// frames.
+///
+/// The output format follows the stream: 4:4:4 streams are decoded to
+/// [`PixelFormat::Yuv444`] or [`PixelFormat::Yuv444_16Bit`], all others to
+/// [`PixelFormat::Nv12`] or [`PixelFormat::P016`] depending on the bit depth.
+/// The frames are cropped to the display area of the stream.
+#[derive(Debug)]
+pub struct DecodePipeline {
+    parser: VideoParser<PipelineHandler>,
+}
+
+impl DecodePipeline {
+    /// Create a [`DecodePipeline`] for a stream of the given codec, which
+    /// decodes on the device of `ctx`.
+    ///
+    /// The decoder is only created once the first sequence header has been
+    /// parsed.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the codec is not supported or if we run out of memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::{cuviddec::cudaVideoCodec, nvEncodeAPI::NV_ENC_CODEC_H264_GUID},
+    /// #     DecodePipeline, EncodePictureParams, Encoder, EncoderInitParams, Frame, PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx.clone()).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// //* Encode some frames. *//
+    /// # let data = vec![0; PixelFormat::Nv12.frame_size(WIDTH, HEIGHT, WIDTH)];
+    /// # let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    /// # let mut pipeline = session.create_encode_pipeline(8).unwrap();
+    /// # let mut packets = Vec::new();
+    /// # for i in 0..32 {
+    /// #     let params = EncodePictureParams {
+    /// #         input_timestamp: i,
+    /// #         ..Default::default()
+    /// #     };
+    /// #     packets.extend(pipeline.encode_frame(&frame, params).unwrap());
+    /// # }
+    /// # packets.extend(pipeline.finish().unwrap());
+    ///
+    /// let mut decoder = DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264).unwrap();
+    /// let mut frames = Vec::new();
+    /// for packet in &packets {
+    ///     frames.extend(decoder.decode(&packet.data, packet.pts as i64).unwrap());
+    /// }
+    /// frames.extend(decoder.finish().unwrap());
+    ///
+    /// assert_eq!(frames.len(), 32);
+    /// assert_eq!((frames[0].width, frames[0].height), (WIDTH, HEIGHT));
+    /// assert_eq!(frames[0].format, PixelFormat::Nv12);
+    /// ```
+    pub fn new(ctx: Arc<CudaContext>, codec: cudaVideoCodec) -> Result<Self, EncodeError> {
+        Self::with_parser_params(ctx, &ParserInitParams::new(codec))
+    }
+
+    /// Create a [`DecodePipeline`] with custom [`ParserInitParams`], for
+    /// example to decode corrupted pictures with
+    /// [`ParserInitParams::error_threshold`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if the codec is not supported or if we run out of memory.
+    pub fn with_parser_params(
+        ctx: Arc<CudaContext>,
+        params: &ParserInitParams,
+    ) -> Result<Self, EncodeError> {
+        let handler = PipelineHandler {
+            ctx,
+            decoder: None,
+            deinterlace_mode: cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Adaptive,
+            av1_operating_point: Av1OperatingPointChoice::default(),
+            clock_rate: params.clock_rate_hz(),
+            format: None,
+            last_pts: None,
+            frame_duration: None,
+            pending_field: None,
+            frames: Vec::new(),
+            sei_messages: HashMap::new(),
+            error: None,
+        };
+        let parser = VideoParser::new(params, handler)?;
+        Ok(Self { parser })
+    }
+
+    /// Specifies how interlaced sequences are deinterlaced. Defaults to
+    /// [`cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Adaptive`].
+    ///
+    /// With `Bob` or `Adaptive` each field of an interlaced frame is returned
+    /// as a separate frame, with the timestamp of the second field halfway to
+    /// the next frame. The second field is therefore only returned together
+    /// with the next frame, or by [`DecodePipeline::finish`] at the end of the
+    /// stream, where its timestamp is estimated from the previous frames. With
+    /// `Weave` the fields are returned interleaved in a single frame.
+    /// Progressive sequences are not affected.
+    ///
+    /// The mode is used for the decoders created for the following sequences.
+    pub fn deinterlace_mode(&mut self, deinterlace_mode: cudaVideoDeinterlaceMode) -> &mut Self {
+        self.parser.handler_mut().deinterlace_mode = deinterlace_mode;
+        self
+    }
+
+    /// Specifies which operating point of a scalable AV1 stream to decode, by
+    /// its index in the sequence header, and whether to output the frames of
+    /// all its spatial layers rather than only the highest one. Defaults to
+    /// operating point 0 without all layers.
+    ///
+    /// If the stream has no such operating point, operating point 0 is
+    /// decoded. When all layers are output, the frames of the lower spatial
+    /// layers are scaled to the size of the sequence. Streams of other codecs
+    /// are not affected.
+    pub fn av1_operating_point(&mut self, index: u32, output_all_layers: bool) -> &mut Self {
+        self.parser.handler_mut().av1_operating_point = Av1OperatingPointChoice {
+            index,
+            output_all_layers,
+        };
+        self
+    }
+
+    /// Decode a chunk of the stream, returning the frames which are ready to
+    /// be displayed.
+    ///
+    /// The `pts` is attached to the frame which starts in this chunk. It can
+    /// be in any unit, the timestamps of deinterlaced second fields are
+    /// interpolated between the timestamps of the frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is invalid or not supported by the
+    /// decoder. Frames which were decoded before the error are returned by
+    /// the next call.
+    pub fn decode(&mut self, data: &[u8], pts: i64) -> Result<Vec<DecodedFrame>, EncodeError> {
+        let result = self.parser.parse(data, pts);
+        self.take_frames(result)
+    }
+
+    /// Signal the end of the stream, returning all remaining frames.
+    ///
+    /// A new stream can be decoded afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the remaining pictures cannot be decoded.
+    pub fn finish(&mut self) -> Result<Vec<DecodedFrame>, EncodeError> {
+        let result = self.parser.flush();
+        self.parser.handler_mut().finish_field();
+        self.take_frames(result)
+    }
+
+    /// Return the displayed frames, or the error of a callback in favour of
+    /// the less helpful error of the parser.
+    fn take_frames(
+        &mut self,
+        result: Result<(), EncodeError>,
+    ) -> Result<Vec<DecodedFrame>, EncodeError> {
+        let handler = self.parser.handler_mut();
+        if let Some(err) = handler.error.take() {
+            return Err(err);
+        }
+        result?;
+        Ok(mem::take(&mut handler.frames))
+    }
+}
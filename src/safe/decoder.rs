@@ -0,0 +1,1022 @@
+//! Defines [`Decoder`], a safe wrapper around an NVDEC video decoder.
+//!
+//! The decoder only decodes pictures, the bitstream has to be parsed first,
+//! for example with a [`VideoParser`](super::VideoParser). Decoded pictures
+//! are mapped with [`Decoder::map_frame`] to read them from device memory.
+
+use std::{ffi::c_ulong, ptr, sync::Arc};
+
+use cudarc::driver::{
+    sys::{cuMemcpy2D_v2, CUmemorytype, CUresult, CUDA_MEMCPY2D},
+    CudaContext,
+    DriverError,
+};
+
+use super::{
+    format::PixelFormat,
+    parser::DisplayInfo,
+    result::{EncodeError, ErrorKind},
+};
+use crate::sys::cuviddec::{
+    cudaVideoChromaFormat,
+    cudaVideoCodec,
+    cudaVideoCreateFlags,
+    cudaVideoDeinterlaceMode,
+    cudaVideoSurfaceFormat,
+    cuvidCreateDecoder,
+    cuvidCtxLockCreate,
+    cuvidCtxLockDestroy,
+    cuvidDecodePicture,
+    cuvidDecodeStatus,
+    cuvidDestroyDecoder,
+    cuvidGetDecodeStatus,
+    cuvidGetDecoderCaps,
+    cuvidMapVideoFrame64,
+    cuvidReconfigureDecoder,
+    cuvidUnmapVideoFrame64,
+    CUvideoctxlock,
+    CUvideodecoder,
+    CUVIDDECODECAPS,
+    CUVIDDECODECREATEINFO,
+    CUVIDGETDECODESTATUS,
+    CUVIDPICPARAMS,
+    CUVIDPROCPARAMS,
+    CUVIDRECONFIGUREDECODERINFO,
+};
+
+/// The output formats in the order of the bits of
+/// [`CUVIDDECODECAPS::nOutputFormatMask`].
+const OUTPUT_FORMATS: [cudaVideoSurfaceFormat; 4] = [
+    cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_NV12,
+    cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_P016,
+    cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_YUV444,
+    cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_YUV444_16Bit,
+];
+
+/// A rectangle in pixels, from the top left corner `(left, top)` up to but
+/// not including the bottom right corner `(right, bottom)`.
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub struct Rect {
+    /// The first column of the rectangle.
+    pub left: u32,
+    /// The first row of the rectangle.
+    pub top: u32,
+    /// The column after the last column of the rectangle.
+    pub right: u32,
+    /// The row after the last row of the rectangle.
+    pub bottom: u32,
+}
+
+impl Rect {
+    /// Create a [`Rect`] from its edges.
+    #[must_use]
+    pub fn new(left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    /// Getter for the width of the rectangle.
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.right.saturating_sub(self.left)
+    }
+
+    /// Getter for the height of the rectangle.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.bottom.saturating_sub(self.top)
+    }
+
+    /// Get the edges as the 16-bit values used by the decoder API, in the
+    /// order left, top, right, bottom.
+    fn to_short(self) -> Result<[i16; 4], EncodeError> {
+        let short = |value: u32| {
+            i16::try_from(value).map_err(|_| {
+                EncodeError::new(
+                    ErrorKind::InvalidParam,
+                    format!("The rectangle {self:?} is too large for the decoder."),
+                )
+            })
+        };
+        Ok([
+            short(self.left)?,
+            short(self.top)?,
+            short(self.right)?,
+            short(self.bottom)?,
+        ])
+    }
+}
+
+/// The capabilities of the decoder for a codec, chroma format and bit depth.
+///
+/// See [`Decoder::get_caps`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderCaps {
+    /// Whether the combination of codec, chroma format and bit depth is
+    /// supported at all.
+    pub supported: bool,
+    /// The number of NVDEC engines on the GPU.
+    pub num_decoders: u32,
+    /// The formats the decoded pictures can be output in.
+    pub output_formats: Vec<PixelFormat>,
+    /// The maximum coded width.
+    pub max_width: u32,
+    /// The maximum coded height.
+    pub max_height: u32,
+    /// The maximum number of 16x16 macroblocks in a picture.
+    pub max_macroblocks: u32,
+    /// The minimum coded width.
+    pub min_width: u32,
+    /// The minimum coded height.
+    pub min_height: u32,
+}
+
+impl From<&CUVIDDECODECAPS> for DecoderCaps {
+    fn from(caps: &CUVIDDECODECAPS) -> Self {
+        let output_formats = OUTPUT_FORMATS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| caps.nOutputFormatMask & (1 << bit) != 0)
+            .map(|(_, &format)| format.into())
+            .collect();
+        Self {
+            supported: caps.bIsSupported != 0,
+            num_decoders: caps.nNumNVDECs.into(),
+            output_formats,
+            max_width: caps.nMaxWidth,
+            max_height: caps.nMaxHeight,
+            max_macroblocks: caps.nMaxMBCount,
+            min_width: caps.nMinWidth.into(),
+            min_height: caps.nMinHeight.into(),
+        }
+    }
+}
+
+/// Wrapper enum around [`cuvidDecodeStatus`], the result of decoding a
+/// picture.
+///
+/// See [`Decoder::status`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum DecodeStatus {
+    /// The status is not known, for example because the picture index is not
+    /// in use.
+    Invalid,
+    /// The picture is still being decoded.
+    InProgress,
+    /// The picture was decoded without errors.
+    Success,
+    /// The picture was decoded, but the bitstream contained errors.
+    Error,
+    /// The picture was decoded, and the errors in the bitstream were
+    /// concealed.
+    ErrorConcealed,
+}
+
+impl DecodeStatus {
+    /// Whether the picture was decoded with errors, concealed or not.
+    #[must_use]
+    pub fn is_error(self) -> bool {
+        matches!(self, Self::Error | Self::ErrorConcealed)
+    }
+}
+
+impl From<cuvidDecodeStatus> for DecodeStatus {
+    fn from(status: cuvidDecodeStatus) -> Self {
+        match status {
+            cuvidDecodeStatus::cuvidDecodeStatus_Invalid => Self::Invalid,
+            cuvidDecodeStatus::cuvidDecodeStatus_InProgress => Self::InProgress,
+            cuvidDecodeStatus::cuvidDecodeStatus_Success => Self::Success,
+            cuvidDecodeStatus::cuvidDecodeStatus_Error => Self::Error,
+            cuvidDecodeStatus::cuvidDecodeStatus_Error_Concealed => Self::ErrorConcealed,
+        }
+    }
+}
+
+/// The parameters for creating a [`Decoder`], which fill in
+/// [`CUVIDDECODECREATEINFO`].
+#[derive(Debug, Clone, Copy)]
+pub struct DecoderInitParams {
+    codec: cudaVideoCodec,
+    width: u32,
+    height: u32,
+    max_width: u32,
+    max_height: u32,
+    chroma_format: cudaVideoChromaFormat,
+    bit_depth: u32,
+    num_decode_surfaces: u32,
+    num_output_surfaces: u32,
+    output_format: PixelFormat,
+    deinterlace_mode: cudaVideoDeinterlaceMode,
+    display_area: Option<Rect>,
+    target_size: Option<(u32, u32)>,
+    letterbox: bool,
+}
+
+impl DecoderInitParams {
+    /// Create a new builder for [`DecoderInitParams`], for pictures with the
+    /// given coded width and height.
+    ///
+    /// By default the pictures are 8-bit 4:2:0 and are output as
+    /// [`PixelFormat::Nv12`] in their full coded size.
+    #[must_use]
+    pub fn new(codec: cudaVideoCodec, width: u32, height: u32) -> Self {
+        Self {
+            codec,
+            width,
+            height,
+            max_width: width,
+            max_height: height,
+            chroma_format: cudaVideoChromaFormat::cudaVideoChromaFormat_420,
+            bit_depth: 8,
+            num_decode_surfaces: 20,
+            num_output_surfaces: 2,
+            output_format: PixelFormat::Nv12,
+            deinterlace_mode: cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Weave,
+            display_area: None,
+            target_size: None,
+            letterbox: false,
+        }
+    }
+
+    /// Specifies the chroma format of the coded pictures.
+    pub fn chroma_format(&mut self, chroma_format: cudaVideoChromaFormat) -> &mut Self {
+        self.chroma_format = chroma_format;
+        self
+    }
+
+    /// Specifies the bit depth of the coded pictures.
+    pub fn bit_depth(&mut self, bit_depth: u32) -> &mut Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// Specifies the number of surfaces the decoder keeps reference and
+    /// decoded pictures in.
+    ///
+    /// This should be the `min_num_decode_surfaces` from the sequence header,
+    /// see [`VideoFormat`](super::VideoFormat). The default of 20 is enough
+    /// for any stream, but wastes memory for most of them.
+    pub fn num_decode_surfaces(&mut self, num_decode_surfaces: u32) -> &mut Self {
+        self.num_decode_surfaces = num_decode_surfaces;
+        self
+    }
+
+    /// Specifies how many frames can be mapped with [`Decoder::map_frame`] at
+    /// the same time. Defaults to 2.
+    pub fn num_output_surfaces(&mut self, num_output_surfaces: u32) -> &mut Self {
+        self.num_output_surfaces = num_output_surfaces;
+        self
+    }
+
+    /// Specifies the format decoded frames are output in.
+    ///
+    /// Only [`PixelFormat::Nv12`], [`PixelFormat::P016`],
+    /// [`PixelFormat::Yuv444`] and [`PixelFormat::Yuv444_16Bit`] can be
+    /// output, and which of them depends on the codec, chroma format and bit
+    /// depth, see [`DecoderCaps::output_formats`].
+    pub fn output_format(&mut self, output_format: PixelFormat) -> &mut Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Specifies the largest coded size the decoder can be reconfigured to
+    /// with [`Decoder::reconfigure`] without recreating it. Defaults to the
+    /// coded size.
+    ///
+    /// The decoder allocates its surfaces for this size, so it should not be
+    /// larger than the largest size the stream switches to.
+    pub fn max_size(&mut self, max_width: u32, max_height: u32) -> &mut Self {
+        self.max_width = max_width;
+        self.max_height = max_height;
+        self
+    }
+
+    /// Specifies how interlaced pictures are output. Defaults to
+    /// [`cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Weave`].
+    ///
+    /// With `Weave` the two fields of an interlaced picture are output
+    /// interleaved as a single frame. With `Bob` and `Adaptive` each field is
+    /// output as a separate full frame, see [`Decoder::num_output_frames`].
+    /// `Adaptive` gives the best quality, and is what NVIDIA recommends for
+    /// interlaced content.
+    pub fn deinterlace_mode(&mut self, deinterlace_mode: cudaVideoDeinterlaceMode) -> &mut Self {
+        self.deinterlace_mode = deinterlace_mode;
+        self
+    }
+
+    /// Crop the coded pictures to `rect` before scaling them.
+    ///
+    /// Without [`DecoderInitParams::resize_to`] or
+    /// [`DecoderInitParams::letterbox`] the output frames have the size of
+    /// the rectangle.
+    pub fn crop(&mut self, rect: Rect) -> &mut Self {
+        self.display_area = Some(rect);
+        self
+    }
+
+    /// Scale the output frames to `width` x `height`, stretching them if the
+    /// aspect ratio differs.
+    pub fn resize_to(&mut self, width: u32, height: u32) -> &mut Self {
+        self.target_size = Some((width, height));
+        self.letterbox = false;
+        self
+    }
+
+    /// Scale the output frames to fit within `width` x `height` while keeping
+    /// their aspect ratio, centering them in frames of exactly that size.
+    ///
+    /// The decoder does not write the bars around the picture, so they are
+    /// not guaranteed to be black.
+    pub fn letterbox(&mut self, width: u32, height: u32) -> &mut Self {
+        self.target_size = Some((width, height));
+        self.letterbox = true;
+        self
+    }
+
+    /// Getter for the area of the coded pictures which is output.
+    fn crop_rect(&self) -> Rect {
+        self.display_area
+            .unwrap_or_else(|| Rect::new(0, 0, self.width, self.height))
+    }
+
+    /// Getter for the size of the output frames.
+    fn output_size(&self) -> (u32, u32) {
+        self.target_size.unwrap_or_else(|| {
+            let crop = self.crop_rect();
+            (crop.width(), crop.height())
+        })
+    }
+
+    /// Getter for the area of the output frames the picture is scaled into,
+    /// or `None` to fill the whole frame.
+    fn target_rect(&self) -> Option<Rect> {
+        if !self.letterbox {
+            return None;
+        }
+        let crop = self.crop_rect();
+        let (crop_width, crop_height) = (u64::from(crop.width()), u64::from(crop.height()));
+        let (target_width, target_height) = self.output_size();
+        let (target_width, target_height) = (u64::from(target_width), u64::from(target_height));
+        // Fill the width or the height, whichever runs out first, and keep the
+        // sizes even for the subsampled chroma planes.
+        let (width, height) = if crop_width * target_height <= target_width * crop_height {
+            (
+                crop_width * target_height / crop_height.max(1),
+                target_height,
+            )
+        } else {
+            (target_width, crop_height * target_width / crop_width.max(1))
+        };
+        let (width, height) = (width & !1, height & !1);
+        let left = ((target_width - width) / 2) & !1;
+        let top = ((target_height - height) / 2) & !1;
+        // The values are at most the target size, which is a `u32`.
+        let edge = |value: u64| u32::try_from(value).unwrap_or(u32::MAX);
+        Some(Rect::new(
+            edge(left),
+            edge(top),
+            edge(left + width),
+            edge(top + height),
+        ))
+    }
+
+    /// Check the crop and the output size against the coded size and the
+    /// capabilities of the decoder.
+    fn validate_geometry(&self, caps: &DecoderCaps) -> Result<(), EncodeError> {
+        let crop = self.crop_rect();
+        if crop.width() == 0
+            || crop.height() == 0
+            || crop.right > self.width
+            || crop.bottom > self.height
+        {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The crop {crop:?} must be a non-empty area of the {}x{} coded pictures.",
+                    self.width, self.height
+                ),
+            ));
+        }
+        let (width, height) = self.output_size();
+        if width < caps.min_width
+            || height < caps.min_height
+            || width > caps.max_width
+            || height > caps.max_height
+        {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The output size {width}x{height} is outside of the supported range from \
+                     {}x{} to {}x{}.",
+                    caps.min_width, caps.min_height, caps.max_width, caps.max_height
+                ),
+            ));
+        }
+        // Check that the rectangles fit the decoder API.
+        crop.to_short()?;
+        if let Some(rect) = self.target_rect() {
+            rect.to_short()?;
+        }
+        Ok(())
+    }
+
+    /// Check the parameters against the capabilities of the decoder.
+    fn validate(&self, caps: &DecoderCaps) -> Result<(), EncodeError> {
+        if !caps.supported {
+            return Err(EncodeError::new(
+                ErrorKind::UnsupportedParam,
+                format!(
+                    "The decoder does not support {:?} with {:?} and a bit depth of {}.",
+                    self.codec, self.chroma_format, self.bit_depth
+                ),
+            ));
+        }
+        if self.width < caps.min_width
+            || self.height < caps.min_height
+            || self.width > caps.max_width
+            || self.height > caps.max_height
+        {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The size {}x{} is outside of the supported range from {}x{} to {}x{}.",
+                    self.width,
+                    self.height,
+                    caps.min_width,
+                    caps.min_height,
+                    caps.max_width,
+                    caps.max_height
+                ),
+            ));
+        }
+        if self.width > self.max_width
+            || self.height > self.max_height
+            || self.max_width > caps.max_width
+            || self.max_height > caps.max_height
+        {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The maximum size {}x{} must be between the size {}x{} and {}x{}.",
+                    self.max_width,
+                    self.max_height,
+                    self.width,
+                    self.height,
+                    caps.max_width,
+                    caps.max_height
+                ),
+            ));
+        }
+        let macroblocks = ((self.width + 15) / 16) * ((self.height + 15) / 16);
+        if macroblocks > caps.max_macroblocks {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The size {}x{} has {macroblocks} macroblocks, but at most {} are supported.",
+                    self.width, self.height, caps.max_macroblocks
+                ),
+            ));
+        }
+        if !caps.output_formats.contains(&self.output_format) {
+            return Err(EncodeError::new(
+                ErrorKind::UnsupportedParam,
+                format!(
+                    "The decoder cannot output {:?}, only {:?}.",
+                    self.output_format, caps.output_formats
+                ),
+            ));
+        }
+        self.validate_geometry(caps)
+    }
+
+    /// Build the [`CUVIDDECODECREATEINFO`] for these parameters.
+    fn create_info(&self, lock: CUvideoctxlock) -> Result<CUVIDDECODECREATEINFO, EncodeError> {
+        let (target_width, target_height) = self.output_size();
+        let [left, top, right, bottom] = self.crop_rect().to_short()?;
+        let target_rect = self.target_rect().unwrap_or_default().to_short()?;
+        let mut create_info = CUVIDDECODECREATEINFO {
+            ulWidth: self.width.into(),
+            ulHeight: self.height.into(),
+            ulNumDecodeSurfaces: self.num_decode_surfaces.into(),
+            CodecType: self.codec,
+            ChromaFormat: self.chroma_format,
+            ulCreationFlags: cudaVideoCreateFlags::cudaVideoCreate_PreferCUVID as c_ulong,
+            bitDepthMinus8: self.bit_depth.saturating_sub(8).into(),
+            ulMaxWidth: self.max_width.into(),
+            ulMaxHeight: self.max_height.into(),
+            OutputFormat: self.output_format.try_into()?,
+            DeinterlaceMode: self.deinterlace_mode,
+            ulTargetWidth: target_width.into(),
+            ulTargetHeight: target_height.into(),
+            ulNumOutputSurfaces: self.num_output_surfaces.into(),
+            vidLock: lock,
+            ..Default::default()
+        };
+        create_info.display_area.left = left;
+        create_info.display_area.top = top;
+        create_info.display_area.right = right;
+        create_info.display_area.bottom = bottom;
+        create_info.target_rect.left = target_rect[0];
+        create_info.target_rect.top = target_rect[1];
+        create_info.target_rect.right = target_rect[2];
+        create_info.target_rect.bottom = target_rect[3];
+        Ok(create_info)
+    }
+}
+
+/// A hardware video decoder.
+///
+/// The decoder is created for a codec, a coded size and an output format,
+/// see [`DecoderInitParams`]. Pictures are decoded with
+/// [`Decoder::decode_picture`] using the parameters produced by a parser, and
+/// read back with [`Decoder::map_frame`].
+///
+/// The CUDA context is locked while the decoder uses it, so a [`Decoder`] can
+/// be used from any thread.
+#[derive(Debug)]
+pub struct Decoder {
+    ptr: CUvideodecoder,
+    lock: CUvideoctxlock,
+    ctx: Arc<CudaContext>,
+    params: DecoderInitParams,
+    caps: DecoderCaps,
+}
+
+// SAFETY: The decoder and the context lock are not tied to a thread, and
+// the decoder locks the CUDA context with the context lock whenever it uses
+// it.
+unsafe impl Send for Decoder {}
+
+impl Decoder {
+    /// Get the capabilities of the decoder on the device of `ctx`, for the
+    /// given codec, chroma format and bit depth.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the CUDA context is invalid.
+    pub fn get_caps(
+        ctx: &Arc<CudaContext>,
+        codec: cudaVideoCodec,
+        chroma_format: cudaVideoChromaFormat,
+        bit_depth: u32,
+    ) -> Result<DecoderCaps, EncodeError> {
+        ctx.bind_to_thread()?;
+        let mut caps = CUVIDDECODECAPS {
+            eCodecType: codec,
+            eChromaFormat: chroma_format,
+            nBitDepthMinus8: bit_depth.saturating_sub(8),
+            ..Default::default()
+        };
+        unsafe { cuvidGetDecoderCaps(&mut caps) }.result()?;
+        Ok(DecoderCaps::from(&caps))
+    }
+
+    /// Create a decoder on the device of `ctx`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::UnsupportedParam`](super::ErrorKind::UnsupportedParam)
+    /// if the device cannot decode the codec, chroma format and bit depth, or
+    /// cannot output the requested format. Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// size is not supported. Could also error if we run out of memory.
+    pub fn new(ctx: Arc<CudaContext>, params: &DecoderInitParams) -> Result<Self, EncodeError> {
+        let caps = Self::get_caps(&ctx, params.codec, params.chroma_format, params.bit_depth)?;
+        params.validate(&caps)?;
+
+        let mut lock = ptr::null_mut();
+        unsafe { cuvidCtxLockCreate(&mut lock, ctx.cu_ctx()) }.result()?;
+        let mut ptr = ptr::null_mut();
+        let result = params.create_info(lock).and_then(|mut create_info| {
+            Ok(unsafe { cuvidCreateDecoder(&mut ptr, &mut create_info) }.result()?)
+        });
+        if let Err(err) = result {
+            // Ignore the error from destroying the lock in favour of the first
+            // one.
+            let _ = unsafe { cuvidCtxLockDestroy(lock) };
+            return Err(err);
+        }
+        Ok(Self {
+            ptr,
+            lock,
+            ctx,
+            params: *params,
+            caps,
+        })
+    }
+
+    /// Getter for the codec.
+    #[must_use]
+    pub fn codec(&self) -> cudaVideoCodec {
+        self.params.codec
+    }
+
+    /// Getter for the chroma format of the coded pictures.
+    #[must_use]
+    pub fn chroma_format(&self) -> cudaVideoChromaFormat {
+        self.params.chroma_format
+    }
+
+    /// Getter for the bit depth of the coded pictures.
+    #[must_use]
+    pub fn bit_depth(&self) -> u32 {
+        self.params.bit_depth
+    }
+
+    /// Getter for the coded width of the pictures.
+    #[must_use]
+    pub fn coded_width(&self) -> u32 {
+        self.params.width
+    }
+
+    /// Getter for the coded height of the pictures.
+    #[must_use]
+    pub fn coded_height(&self) -> u32 {
+        self.params.height
+    }
+
+    /// Getter for the largest coded width the decoder can be reconfigured to.
+    #[must_use]
+    pub fn max_width(&self) -> u32 {
+        self.params.max_width
+    }
+
+    /// Getter for the largest coded height the decoder can be reconfigured
+    /// to.
+    #[must_use]
+    pub fn max_height(&self) -> u32 {
+        self.params.max_height
+    }
+
+    /// Getter for the width of the output frames.
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.params.output_size().0
+    }
+
+    /// Getter for the height of the output frames.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.params.output_size().1
+    }
+
+    /// Getter for the format of the output frames.
+    #[must_use]
+    pub fn output_format(&self) -> PixelFormat {
+        self.params.output_format
+    }
+
+    /// Getter for the deinterlace mode.
+    #[must_use]
+    pub fn deinterlace_mode(&self) -> cudaVideoDeinterlaceMode {
+        self.params.deinterlace_mode
+    }
+
+    /// Getter for the number of decode surfaces.
+    #[must_use]
+    pub fn num_decode_surfaces(&self) -> u32 {
+        self.params.num_decode_surfaces
+    }
+
+    /// Getter for the capabilities of the decoder, which the parameters were
+    /// validated against.
+    #[must_use]
+    pub fn caps(&self) -> &DecoderCaps {
+        &self.caps
+    }
+
+    /// Getter for the CUDA context the decoder runs on.
+    #[must_use]
+    pub fn cuda_ctx(&self) -> &Arc<CudaContext> {
+        &self.ctx
+    }
+
+    /// Start decoding a picture.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the picture parameters are invalid.
+    ///
+    /// # Safety
+    ///
+    /// The pointers in `picture` must point to valid slice data. This is the
+    /// case for the parameters passed to
+    /// [`ParserHandler::decode_picture`](super::ParserHandler::decode_picture).
+    pub unsafe fn decode_picture(&self, picture: &mut CUVIDPICPARAMS) -> Result<(), EncodeError> {
+        Ok(cuvidDecodePicture(self.raw()?, picture).result()?)
+    }
+
+    /// Change the coded size, the `display_area` of the coded pictures which
+    /// is output, and the size the output is scaled to, for example when the
+    /// resolution of an adaptive bitrate stream changes.
+    ///
+    /// If the coded size fits within [`Decoder::max_width`] and
+    /// [`Decoder::max_height`] the decoder is reconfigured in place,
+    /// otherwise it is destroyed and created again with the new size as its
+    /// maximum size, so the old and the new surfaces do not need memory at the
+    /// same time. Either way the decoded pictures are discarded, so this
+    /// should be called from
+    /// [`ParserHandler::sequence`](super::ParserHandler::sequence).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// display area does not fit within the coded size, or if the new sizes
+    /// are not supported. Could also error if we run out of memory, in which
+    /// case the decoder cannot be used until it is reconfigured successfully.
+    pub fn reconfigure(
+        &mut self,
+        width: u32,
+        height: u32,
+        display_area: Rect,
+        target_size: (u32, u32),
+    ) -> Result<(), EncodeError> {
+        let mut params = self.params;
+        params.width = width;
+        params.height = height;
+        params.display_area = Some(display_area);
+        params.target_size = Some(target_size);
+        params.validate_geometry(&self.caps)?;
+
+        let grows = width > self.params.max_width || height > self.params.max_height;
+        if grows || self.ptr.is_null() {
+            if grows {
+                params.max_size(width, height);
+            }
+            return self.recreate(&params);
+        }
+
+        let [left, top, right, bottom] = display_area.to_short()?;
+        let target_rect = params.target_rect().unwrap_or_default().to_short()?;
+        let mut info = CUVIDRECONFIGUREDECODERINFO {
+            ulWidth: width,
+            ulHeight: height,
+            ulTargetWidth: target_size.0,
+            ulTargetHeight: target_size.1,
+            ulNumDecodeSurfaces: self.params.num_decode_surfaces,
+            ..Default::default()
+        };
+        info.display_area.left = left;
+        info.display_area.top = top;
+        info.display_area.right = right;
+        info.display_area.bottom = bottom;
+        info.target_rect.left = target_rect[0];
+        info.target_rect.top = target_rect[1];
+        info.target_rect.right = target_rect[2];
+        info.target_rect.bottom = target_rect[3];
+        self.ctx.bind_to_thread()?;
+        unsafe { cuvidReconfigureDecoder(self.raw()?, &mut info) }.result()?;
+        self.params = params;
+        Ok(())
+    }
+
+    /// Destroy the decoder and create it again with `params`, keeping the
+    /// context lock. If creating it fails, the handle stays null.
+    fn recreate(&mut self, params: &DecoderInitParams) -> Result<(), EncodeError> {
+        params.validate(&self.caps)?;
+        let mut create_info = params.create_info(self.lock)?;
+        if !self.ptr.is_null() {
+            self.ctx.bind_to_thread()?;
+            unsafe { cuvidDestroyDecoder(self.ptr) }.result()?;
+            self.ptr = ptr::null_mut();
+        }
+        let mut ptr = ptr::null_mut();
+        unsafe { cuvidCreateDecoder(&mut ptr, &mut create_info) }.result()?;
+        self.ptr = ptr;
+        self.params = *params;
+        Ok(())
+    }
+
+    /// Getter for the decoder handle.
+    fn raw(&self) -> Result<CUvideodecoder, EncodeError> {
+        if self.ptr.is_null() {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "The decoder could not be recreated, it has to be reconfigured again.",
+            ));
+        }
+        Ok(self.ptr)
+    }
+
+    /// Get the status of decoding the picture at `picture_index`.
+    ///
+    /// The status is only available on Maxwell and newer GPUs, and only for
+    /// H.264, HEVC and JPEG.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::UnsupportedParam`](super::ErrorKind::UnsupportedParam) if
+    /// the GPU or the codec does not support getting the status. Could also
+    /// error if the picture index is invalid.
+    pub fn status(&self, picture_index: i32) -> Result<DecodeStatus, EncodeError> {
+        let mut status = CUVIDGETDECODESTATUS::default();
+        self.ctx.bind_to_thread()?;
+        match unsafe { cuvidGetDecodeStatus(self.raw()?, picture_index, &mut status) }.result() {
+            Ok(()) => Ok(status.decodeStatus.into()),
+            Err(DriverError(CUresult::CUDA_ERROR_NOT_SUPPORTED)) => Err(EncodeError::new(
+                ErrorKind::UnsupportedParam,
+                format!(
+                    "The GPU does not report the decode status of {:?} pictures.",
+                    self.params.codec
+                ),
+            )),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Get the number of frames a displayed picture is output as.
+    ///
+    /// This is 2 for interlaced pictures with both fields when deinterlacing
+    /// with `Bob` or `Adaptive`, and 1 otherwise. The second frame is mapped
+    /// with [`Decoder::map_field`].
+    #[must_use]
+    pub fn num_output_frames(&self, info: &DisplayInfo) -> u32 {
+        let deinterlaced = self.params.deinterlace_mode
+            != cudaVideoDeinterlaceMode::cudaVideoDeinterlaceMode_Weave;
+        // A negative `repeat_first_field` marks an unpaired field.
+        if deinterlaced && !info.progressive_frame && info.repeat_first_field >= 0 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Map a decoded picture so it can be read.
+    ///
+    /// This waits until the picture has been decoded. The frame is unmapped
+    /// when the [`MappedFrame`] is dropped, and at most
+    /// [`DecoderInitParams::num_output_surfaces`] frames can be mapped at the
+    /// same time.
+    ///
+    /// For deinterlaced pictures this maps the first field, see
+    /// [`Decoder::map_field`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if the picture index is invalid or if too many frames are
+    /// mapped.
+    pub fn map_frame(&self, info: &DisplayInfo) -> Result<MappedFrame<'_>, EncodeError> {
+        self.map_field(info, false)
+    }
+
+    /// Map the first or the second field of a decoded picture, deinterlaced
+    /// into a full frame.
+    ///
+    /// The second field only exists if [`Decoder::num_output_frames`] is 2.
+    /// Otherwise this is the same as [`Decoder::map_frame`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if the picture index is invalid or if too many frames are
+    /// mapped.
+    pub fn map_field(
+        &self,
+        info: &DisplayInfo,
+        second_field: bool,
+    ) -> Result<MappedFrame<'_>, EncodeError> {
+        let mut proc_params = CUVIDPROCPARAMS {
+            progressive_frame: info.progressive_frame.into(),
+            second_field: second_field.into(),
+            top_field_first: info.top_field_first.into(),
+            unpaired_field: (info.repeat_first_field < 0).into(),
+            ..Default::default()
+        };
+        let decoder = self.raw()?;
+        let mut device_ptr = 0;
+        let mut pitch = 0;
+        self.ctx.bind_to_thread()?;
+        unsafe {
+            cuvidMapVideoFrame64(
+                decoder,
+                info.picture_index,
+                &mut device_ptr,
+                &mut pitch,
+                &mut proc_params,
+            )
+        }
+        .result()?;
+        Ok(MappedFrame {
+            decoder: self,
+            device_ptr,
+            pitch,
+        })
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        self.ctx
+            .bind_to_thread()
+            .expect("The CUDA context should be valid.");
+        // The handle is null if recreating the decoder failed.
+        if !self.ptr.is_null() {
+            unsafe { cuvidDestroyDecoder(self.ptr) }
+                .result()
+                .expect("The decoder pointer should be valid.");
+        }
+        unsafe { cuvidCtxLockDestroy(self.lock) }
+            .result()
+            .expect("The context lock should be valid.");
+    }
+}
+
+/// A decoded frame mapped into device memory.
+///
+/// The frame is laid out like a [`Frame`](super::Frame) in the output format
+/// of the decoder, except that the chroma planes start at a row which is a
+/// multiple of two. Use [`MappedFrame::copy_to_host`] to get the frame in
+/// host memory.
+///
+/// The frame is unmapped when dropped.
+#[derive(Debug)]
+pub struct MappedFrame<'a> {
+    decoder: &'a Decoder,
+    device_ptr: u64,
+    pitch: u32,
+}
+
+impl MappedFrame<'_> {
+    /// Getter for the device pointer to the frame.
+    #[must_use]
+    pub fn device_ptr(&self) -> u64 {
+        self.device_ptr
+    }
+
+    /// Getter for the pitch of the luma plane in bytes.
+    #[must_use]
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Getter for the width of the frame in pixels.
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.decoder.width()
+    }
+
+    /// Getter for the height of the frame in pixels.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.decoder.height()
+    }
+
+    /// Getter for the pixel format of the frame.
+    #[must_use]
+    pub fn format(&self) -> PixelFormat {
+        self.decoder.output_format()
+    }
+
+    /// Copy the frame into host memory.
+    ///
+    /// The returned data is tightly packed, so its pitch is
+    /// [`PixelFormat::min_pitch`] of the width.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the copy fails.
+    pub fn copy_to_host(&self) -> Result<Vec<u8>, EncodeError> {
+        let (format, width, height) = (self.format(), self.width(), self.height());
+        let pitch = format.min_pitch(width);
+        let mut data = vec![0; format.frame_size(width, height, pitch)];
+        // The planes of the decoder output start at even rows.
+        let plane_size = self.pitch as usize * ((height as usize + 1) & !1);
+
+        self.decoder.ctx.bind_to_thread()?;
+        let mut offset = 0;
+        for (i, plane) in format.plane_layouts(width, height).iter().enumerate() {
+            let copy = CUDA_MEMCPY2D {
+                srcMemoryType: CUmemorytype::CU_MEMORYTYPE_DEVICE,
+                srcDevice: self.device_ptr + (plane_size * i) as u64,
+                srcPitch: self.pitch as usize,
+                dstMemoryType: CUmemorytype::CU_MEMORYTYPE_HOST,
+                dstHost: data[offset..].as_mut_ptr().cast(),
+                dstPitch: pitch as usize,
+                WidthInBytes: plane.row_bytes,
+                Height: plane.rows,
+                ..Default::default()
+            };
+            unsafe { cuMemcpy2D_v2(&copy) }.result()?;
+            offset += pitch as usize * plane.rows;
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for MappedFrame<'_> {
+    fn drop(&mut self) {
+        self.decoder
+            .ctx
+            .bind_to_thread()
+            .expect("The CUDA context should be valid.");
+        unsafe { cuvidUnmapVideoFrame64(self.decoder.ptr, self.device_ptr) }
+            .result()
+            .expect("The frame should be mapped.");
+    }
+}
@@ -0,0 +1,117 @@
+//! Defines the [`EncodeDevice`] trait and the devices which can be used
+//! for encoding.
+//!
+//! The encode device determines which kinds of external resources can be
+//! registered as encoder input. The [`Encoder`](super::Encoder) and
+//! [`Session`](super::Session) are generic in the device, so that only the
+//! registration functions valid for the device are available.
+
+use std::{ffi::c_void, fmt::Debug, sync::Arc};
+
+use cudarc::driver::CudaContext;
+
+use crate::sys::nvEncodeAPI::NV_ENC_DEVICE_TYPE;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A device which can be used for encoding.
+///
+/// This trait is sealed, it is implemented for [`CudaDevice`], for
+/// [`OpenGlDevice`] on Linux, and for `DirectXDevice` on Windows.
+pub trait EncodeDevice: sealed::Sealed + Debug {
+    /// The device type passed to the encoder.
+    const DEVICE_TYPE: NV_ENC_DEVICE_TYPE;
+
+    /// Get the device pointer passed to the encoder.
+    fn device_ptr(&self) -> *mut c_void;
+}
+
+/// CUDA as the encode device.
+///
+/// This is the default device. Encoders are created with it using
+/// [`Encoder::initialize_with_cuda`](super::Encoder::initialize_with_cuda).
+#[derive(Debug, Clone)]
+pub struct CudaDevice {
+    pub(crate) ctx: Arc<CudaContext>,
+}
+
+impl CudaDevice {
+    /// Getter for the CUDA context used by the encoder.
+    #[must_use]
+    pub fn context(&self) -> &Arc<CudaContext> {
+        &self.ctx
+    }
+}
+
+impl sealed::Sealed for CudaDevice {}
+
+impl EncodeDevice for CudaDevice {
+    const DEVICE_TYPE: NV_ENC_DEVICE_TYPE = NV_ENC_DEVICE_TYPE::NV_ENC_DEVICE_TYPE_CUDA;
+
+    fn device_ptr(&self) -> *mut c_void {
+        // Pass the CUDA Context as the device.
+        // valid casting since CUcontext is a *mut
+        self.ctx.cu_ctx().cast::<c_void>()
+    }
+}
+
+/// OpenGL as the encode device.
+///
+/// The encoder uses the OpenGL context which is current on the calling
+/// thread. Encoders are created with it using
+/// [`Encoder::initialize_with_opengl`](super::Encoder::initialize_with_opengl).
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenGlDevice;
+
+#[cfg(target_os = "linux")]
+impl sealed::Sealed for OpenGlDevice {}
+
+#[cfg(target_os = "linux")]
+impl EncodeDevice for OpenGlDevice {
+    const DEVICE_TYPE: NV_ENC_DEVICE_TYPE = NV_ENC_DEVICE_TYPE::NV_ENC_DEVICE_TYPE_OPENGL;
+
+    fn device_ptr(&self) -> *mut c_void {
+        // The device must be null, the current OpenGL context is used.
+        std::ptr::null_mut()
+    }
+}
+
+/// DirectX as the encode device.
+///
+/// Encoders are created with it using
+/// [`Encoder::initialize_with_directx`](super::Encoder::initialize_with_directx).
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Copy)]
+pub struct DirectXDevice {
+    device: *mut c_void,
+}
+
+#[cfg(target_os = "windows")]
+impl DirectXDevice {
+    /// Create a [`DirectXDevice`] from a raw device pointer.
+    ///
+    /// # Safety
+    ///
+    /// `device` must be a valid pointer to an `ID3D11Device`, `ID3D10Device`,
+    /// `IDirect3DDevice9`, or `ID3D12Device`, and the device must outlive the
+    /// encoder.
+    #[must_use]
+    pub unsafe fn new(device: *mut c_void) -> Self {
+        Self { device }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl sealed::Sealed for DirectXDevice {}
+
+#[cfg(target_os = "windows")]
+impl EncodeDevice for DirectXDevice {
+    const DEVICE_TYPE: NV_ENC_DEVICE_TYPE = NV_ENC_DEVICE_TYPE::NV_ENC_DEVICE_TYPE_DIRECTX;
+
+    fn device_ptr(&self) -> *mut c_void {
+        self.device
+    }
+}
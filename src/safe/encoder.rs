@@ -4,18 +4,33 @@
 //! encoder API. This module also defines builders for some of the parameter
 //! structs used by the interface.
 
-use std::{ffi::c_void, ptr, sync::Arc};
+use std::{cell::Cell, ffi::c_void, ops::Deref, ptr, sync::Arc};
 
 use cudarc::driver::CudaContext;
 
-use super::{api::ENCODE_API, result::EncodeError, session::Session};
+#[cfg(target_os = "windows")]
+use super::device::DirectXDevice;
+#[cfg(target_os = "linux")]
+use super::device::OpenGlDevice;
+use super::{
+    api::ENCODE_API,
+    device::{CudaDevice, EncodeDevice},
+    result::{EncodeError, ErrorKind},
+    session::Session,
+    shared::SessionOwner,
+};
 use crate::sys::nvEncodeAPI::{
     GUID,
     NVENCAPI_VERSION,
     NV_ENC_BUFFER_FORMAT,
+    NV_ENC_CAPS,
+    NV_ENC_CAPS_PARAM,
+    NV_ENC_CAPS_PARAM_VER,
+    NV_ENC_CODEC_AV1_GUID,
+    NV_ENC_CODEC_H264_GUID,
+    NV_ENC_CODEC_HEVC_GUID,
     NV_ENC_CONFIG,
     NV_ENC_CONFIG_VER,
-    NV_ENC_DEVICE_TYPE,
     NV_ENC_INITIALIZE_PARAMS,
     NV_ENC_INITIALIZE_PARAMS_VER,
     NV_ENC_OPEN_ENCODE_SESSION_EX_PARAMS,
@@ -44,12 +59,51 @@ use crate::sys::nvEncodeAPI::{
 /// This type has further function to create input and output buffers
 /// and encode pictures.
 ///
+/// The encoder is generic in its [`EncodeDevice`], which defaults to
+/// [`CudaDevice`]. The [`Session`] only provides the functions for
+/// registering external resources which are valid for the device.
+///
 /// See [NVIDIA Video Codec SDK - Video Encoder API Programming Guide](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html).
 #[derive(Debug)]
-pub struct Encoder {
+pub struct Encoder<D: EncodeDevice = CudaDevice> {
+    // Declared before the device so that the encoder is destroyed first.
+    pub(crate) raw: RawEncoder,
+    pub(crate) device: D,
+}
+
+// The CUDA context is passed to the encoder explicitly, so it does not
+// matter which thread uses the encoder.
+unsafe impl Send for Encoder<CudaDevice> {}
+
+/// The encoder pointer, without the encode device.
+///
+/// Buffers and registered resources keep a reference to this, so that they
+/// do not depend on the type of the device.
+#[derive(Debug)]
+pub(crate) struct RawEncoder {
     pub(crate) ptr: *mut c_void,
-    // Used to fetch the device pointer for an externally allocated buffer
-    pub(crate) ctx: Arc<CudaContext>,
+}
+
+/// A reference to the [`RawEncoder`] held by buffers and registered
+/// resources.
+///
+/// Resources created through a [`SharedSession`](super::SharedSession) keep
+/// the session alive, so they do not borrow it.
+#[derive(Debug)]
+pub(crate) enum EncoderRef<'a> {
+    Borrowed(&'a RawEncoder),
+    Shared(Arc<SessionOwner>),
+}
+
+impl Deref for EncoderRef<'_> {
+    type Target = RawEncoder;
+
+    fn deref(&self) -> &RawEncoder {
+        match self {
+            Self::Borrowed(raw) => raw,
+            Self::Shared(owner) => owner.raw(),
+        }
+    }
 }
 
 /// The client must flush the encoder before freeing any resources.
@@ -62,15 +116,15 @@ pub struct Encoder {
 /// The client must free all the input and output resources before
 /// destroying the encoder.
 /// If using events, they must also be unregistered.
-impl Drop for Encoder {
+impl Drop for RawEncoder {
     fn drop(&mut self) {
         unsafe { (ENCODE_API.destroy_encoder)(self.ptr) }
-            .result(self)
+            .result_raw(self)
             .expect("The encoder pointer should be valid.");
     }
 }
 
-impl Encoder {
+impl Encoder<CudaDevice> {
     /// Create an [`Encoder`] with CUDA as the encode device.
     ///
     /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#cuda).
@@ -89,14 +143,61 @@ impl Encoder {
     /// let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
     /// ```
     pub fn initialize_with_cuda(cuda_ctx: Arc<CudaContext>) -> Result<Self, EncodeError> {
+        Self::open_encode_session(CudaDevice { ctx: cuda_ctx })
+    }
+
+    /// Get the CUDA context of the encoder.
+    pub(crate) fn cuda_ctx(&self) -> &Arc<CudaContext> {
+        self.device.context()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Encoder<OpenGlDevice> {
+    /// Create an [`Encoder`] with OpenGL as the encode device.
+    ///
+    /// The encoder uses the OpenGL context which is current on the calling
+    /// thread, so a context must be made current before calling this. All
+    /// further calls to the encoder must be made from a thread where the same
+    /// context is current. Input textures are registered using
+    /// [`Session::register_gl_texture`].
+    ///
+    /// OpenGL is only supported as an encode device on Linux.
+    ///
+    /// # Errors
+    ///
+    /// Could error if there was no encode capable device detected
+    /// or if no OpenGL context is current.
+    pub fn initialize_with_opengl() -> Result<Self, EncodeError> {
+        Self::open_encode_session(OpenGlDevice)
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Encoder<DirectXDevice> {
+    /// Create an [`Encoder`] with DirectX as the encode device.
+    ///
+    /// DirectX is only supported as an encode device on Windows. Input
+    /// textures can be registered using
+    /// [`Session::register_generic_resource`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if there was no encode capable device detected
+    /// or if the encode device was invalid.
+    pub fn initialize_with_directx(device: DirectXDevice) -> Result<Self, EncodeError> {
+        Self::open_encode_session(device)
+    }
+}
+
+impl<D: EncodeDevice> Encoder<D> {
+    fn open_encode_session(device: D) -> Result<Self, EncodeError> {
         let mut encoder = ptr::null_mut();
         let mut session_params = NV_ENC_OPEN_ENCODE_SESSION_EX_PARAMS {
             version: NV_ENC_OPEN_ENCODE_SESSION_EX_PARAMS_VER,
-            deviceType: NV_ENC_DEVICE_TYPE::NV_ENC_DEVICE_TYPE_CUDA,
+            deviceType: D::DEVICE_TYPE,
             apiVersion: NVENCAPI_VERSION,
-            // Pass the CUDA Context as the device.
-            // valid casting since CUcontext is a *mut
-            device: cuda_ctx.cu_ctx().cast::<c_void>(),
+            device: device.device_ptr(),
             ..Default::default()
         };
 
@@ -110,14 +211,16 @@ impl Encoder {
         }
 
         Ok(Self {
-            ptr: encoder,
-            ctx: cuda_ctx,
+            raw: RawEncoder { ptr: encoder },
+            device,
         })
     }
 
-    // TODO:
-    // - Make Encoder generic in Device.
-    // - Add functions to create Encoder from other encode devices.
+    /// Getter for the encode device.
+    #[must_use]
+    pub fn device(&self) -> &D {
+        &self.device
+    }
 
     /// Get the encode GUIDs which the encoder supports.
     ///
@@ -145,14 +248,14 @@ impl Encoder {
     pub fn get_encode_guids(&self) -> Result<Vec<GUID>, EncodeError> {
         // Query number of supported encoder codec GUIDs.
         let mut supported_count = 0;
-        unsafe { (ENCODE_API.get_encode_guid_count)(self.ptr, &mut supported_count) }
+        unsafe { (ENCODE_API.get_encode_guid_count)(self.raw.ptr, &mut supported_count) }
             .result(self)?;
         // Get the supported GUIDs.
         let mut encode_guids = vec![GUID::default(); supported_count as usize];
         let mut actual_count = 0;
         unsafe {
             (ENCODE_API.get_encode_guids)(
-                self.ptr,
+                self.raw.ptr,
                 encode_guids.as_mut_ptr(),
                 supported_count,
                 &mut actual_count,
@@ -198,14 +301,16 @@ impl Encoder {
     pub fn get_preset_guids(&self, encode_guid: GUID) -> Result<Vec<GUID>, EncodeError> {
         // Query the number of preset GUIDS.
         let mut preset_count = 0;
-        unsafe { (ENCODE_API.get_encode_preset_count)(self.ptr, encode_guid, &mut preset_count) }
-            .result(self)?;
+        unsafe {
+            (ENCODE_API.get_encode_preset_count)(self.raw.ptr, encode_guid, &mut preset_count)
+        }
+        .result(self)?;
         // Get the preset GUIDs.
         let mut actual_count = 0;
         let mut preset_guids = vec![GUID::default(); preset_count as usize];
         unsafe {
             (ENCODE_API.get_encode_preset_guids)(
-                self.ptr,
+                self.raw.ptr,
                 encode_guid,
                 preset_guids.as_mut_ptr(),
                 preset_count,
@@ -253,7 +358,11 @@ impl Encoder {
         // Query the number of profile GUIDs.
         let mut profile_count = 0;
         unsafe {
-            (ENCODE_API.get_encode_profile_guid_count)(self.ptr, encode_guid, &mut profile_count)
+            (ENCODE_API.get_encode_profile_guid_count)(
+                self.raw.ptr,
+                encode_guid,
+                &mut profile_count,
+            )
         }
         .result(self)?;
         // Get the profile GUIDs.
@@ -261,7 +370,7 @@ impl Encoder {
         let mut actual_count = 0;
         unsafe {
             (ENCODE_API.get_encode_profile_guids)(
-                self.ptr,
+                self.raw.ptr,
                 encode_guid,
                 profile_guids.as_mut_ptr(),
                 profile_count,
@@ -313,15 +422,17 @@ impl Encoder {
     ) -> Result<Vec<NV_ENC_BUFFER_FORMAT>, EncodeError> {
         // Query the number of supported input formats.
         let mut format_count = 0;
-        unsafe { (ENCODE_API.get_input_format_count)(self.ptr, encode_guid, &mut format_count) }
-            .result(self)?;
+        unsafe {
+            (ENCODE_API.get_input_format_count)(self.raw.ptr, encode_guid, &mut format_count)
+        }
+        .result(self)?;
         // Get the supported input formats.
         let mut supported_input_formats =
             vec![NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_UNDEFINED; format_count as usize];
         let mut actual_count = 0;
         unsafe {
             (ENCODE_API.get_input_formats)(
-                self.ptr,
+                self.raw.ptr,
                 encode_guid,
                 supported_input_formats.as_mut_ptr(),
                 format_count,
@@ -333,6 +444,63 @@ impl Encoder {
         Ok(supported_input_formats)
     }
 
+    /// Query a capability of the encoder for the given codec GUID.
+    ///
+    /// Most capabilities are flags where `0` means the feature is not
+    /// supported and `1` means it is. Others report a limit, such as the
+    /// maximum width or the number of temporal layers. See [`NV_ENC_CAPS`]
+    /// for what each capability returns.
+    ///
+    /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#querying-encoder-capabilities).
+    ///
+    /// # Errors
+    ///
+    /// Could error if the encode GUID is invalid
+    /// or the capability is not recognized by the driver.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::{NV_ENC_CAPS, NV_ENC_CODEC_H264_GUID},
+    /// #     Encoder,
+    /// # };
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Check if H.264 encoding is supported. *//
+    /// # let encode_guids = encoder.get_encode_guids().unwrap();
+    /// # assert!(encode_guids.contains(&NV_ENC_CODEC_H264_GUID));
+    ///
+    /// let max_width = encoder
+    ///     .get_encode_caps(NV_ENC_CODEC_H264_GUID, NV_ENC_CAPS::NV_ENC_CAPS_WIDTH_MAX)
+    ///     .unwrap();
+    /// assert!(max_width >= 1920);
+    /// ```
+    pub fn get_encode_caps(
+        &self,
+        encode_guid: GUID,
+        caps_to_query: NV_ENC_CAPS,
+    ) -> Result<i32, EncodeError> {
+        let mut caps_param = NV_ENC_CAPS_PARAM {
+            version: NV_ENC_CAPS_PARAM_VER,
+            capsToQuery: caps_to_query,
+            ..Default::default()
+        };
+        let mut caps_value = 0;
+        unsafe {
+            (ENCODE_API.get_encode_caps)(
+                self.raw.ptr,
+                encode_guid,
+                &mut caps_param,
+                &mut caps_value,
+            )
+        }
+        .result(self)?;
+        Ok(caps_value)
+    }
+
     /// Get the preset config struct from the given codec GUID, preset GUID,
     /// and tuning info.
     ///
@@ -395,7 +563,7 @@ impl Encoder {
         };
         unsafe {
             (ENCODE_API.get_encode_preset_config_ex)(
-                self.ptr,
+                self.raw.ptr,
                 encode_guid,
                 preset_guid,
                 tuning_info,
@@ -412,6 +580,10 @@ impl Encoder {
     /// You should use the [`NV_ENC_INITIALIZE_PARAMS`] builder
     /// via [`NV_ENC_INITIALIZE_PARAMS::new`].
     ///
+    /// The `buffer_format` of the input can be given either as a
+    /// [`PixelFormat`](super::PixelFormat) or as a raw
+    /// [`NV_ENC_BUFFER_FORMAT`].
+    ///
     /// See [NVIDIA docs](https://docs.nvidia.com/video-technologies/video-codec-sdk/12.0/nvenc-video-encoder-api-prog-guide/index.html#initializing-the-hardware-encoder-session).
     ///
     /// # Errors
@@ -419,6 +591,12 @@ impl Encoder {
     /// Could error if the `initialize_params` are invalid
     /// or if we run out of memory.
     ///
+    /// Returns an error with
+    /// [`ErrorKind::UnsupportedParam`](super::ErrorKind::UnsupportedParam) if
+    /// the encode config requests more temporal layers than the encoder
+    /// supports for the codec, or enables an intra refresh mode the encoder
+    /// does not support.
+    ///
     /// # Examples
     ///
     /// ```
@@ -447,21 +625,152 @@ impl Encoder {
     /// ```
     pub fn start_session(
         self,
-        buffer_format: NV_ENC_BUFFER_FORMAT,
+        buffer_format: impl Into<NV_ENC_BUFFER_FORMAT>,
         mut initialize_params: EncoderInitParams<'_>,
-    ) -> Result<Session, EncodeError> {
+    ) -> Result<Session<D>, EncodeError> {
+        let buffer_format = buffer_format.into();
         let initialize_params = &mut initialize_params.param;
         let width = initialize_params.encodeWidth;
         let height = initialize_params.encodeHeight;
-        unsafe { (ENCODE_API.initialize_encoder)(self.ptr, initialize_params) }.result(&self)?;
+        // The config pointer is either null or borrowed for the lifetime
+        // of the initialize params, so it is valid to read here.
+        let encode_config = unsafe { initialize_params.encodeConfig.as_ref() };
+        let rc_params = encode_config
+            .map(|config| config.rcParams)
+            .unwrap_or_default();
+        let alpha_layer_encoding = initialize_params.encodeGUID == NV_ENC_CODEC_HEVC_GUID
+            && encode_config.is_some_and(|config| {
+                unsafe { config.encodeCodecConfig.hevcConfig }.enableAlphaLayerEncoding() != 0
+            });
+        let intra_refresh = encode_config
+            .and_then(|config| intra_refresh_config(initialize_params.encodeGUID, config))
+            .is_some();
+        // Without a config the preset decides whether B frames are used.
+        let frame_interval_p = match encode_config {
+            Some(config) => config.frameIntervalP,
+            None => self
+                .get_preset_config(
+                    initialize_params.encodeGUID,
+                    initialize_params.presetGUID,
+                    initialize_params.tuningInfo,
+                )
+                .map_or(1, |preset_config| preset_config.presetCfg.frameIntervalP),
+        };
+        if let Some(config) = encode_config {
+            self.validate_config(initialize_params.encodeGUID, config)?;
+        }
+        unsafe { (ENCODE_API.initialize_encoder)(self.raw.ptr, initialize_params) }
+            .result(&self)?;
         Ok(Session {
             encoder: self,
             width,
             height,
             buffer_format,
             encode_guid: initialize_params.encodeGUID,
+            rc_params,
+            num_state_buffers: initialize_params.numStateBuffers,
+            recon_frame_output: initialize_params.enableReconFrameOutput() != 0,
+            alpha_layer_encoding,
+            intra_refresh,
+            pending_intra_refresh: Cell::new(0),
+            reorder_delay: u32::try_from(frame_interval_p - 1).unwrap_or(0),
         })
     }
+
+    /// Check the codec config against the encoder capabilities, so that
+    /// unsupported features are reported with a descriptive error.
+    fn validate_config(
+        &self,
+        encode_guid: GUID,
+        config: &NV_ENC_CONFIG,
+    ) -> Result<(), EncodeError> {
+        let num_temporal_layers = if encode_guid == NV_ENC_CODEC_H264_GUID {
+            let h264_config = unsafe { config.encodeCodecConfig.h264Config };
+            if h264_config.enableTemporalSVC() == 0 {
+                1
+            } else {
+                if self
+                    .get_encode_caps(encode_guid, NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_TEMPORAL_SVC)?
+                    == 0
+                {
+                    return Err(EncodeError::new(
+                        ErrorKind::UnsupportedParam,
+                        "The encoder does not support temporal SVC for this codec.",
+                    ));
+                }
+                h264_config.numTemporalLayers
+            }
+        } else if encode_guid == NV_ENC_CODEC_HEVC_GUID {
+            unsafe { config.encodeCodecConfig.hevcConfig }.maxTemporalLayersMinus1 + 1
+        } else {
+            1
+        };
+        if num_temporal_layers > 1 {
+            let max_temporal_layers = self.get_encode_caps(
+                encode_guid,
+                NV_ENC_CAPS::NV_ENC_CAPS_NUM_MAX_TEMPORAL_LAYERS,
+            )?;
+            if num_temporal_layers > u32::try_from(max_temporal_layers).unwrap_or(0) {
+                return Err(EncodeError::new(
+                    ErrorKind::UnsupportedParam,
+                    format!(
+                        "The encoder supports at most {max_temporal_layers} temporal layers for \
+                         this codec, but {num_temporal_layers} were requested."
+                    ),
+                ));
+            }
+        }
+        if let Some(single_slice) = intra_refresh_config(encode_guid, config) {
+            if self.get_encode_caps(encode_guid, NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_INTRA_REFRESH)?
+                == 0
+            {
+                return Err(EncodeError::new(
+                    ErrorKind::UnsupportedParam,
+                    "The encoder does not support intra refresh for this codec.",
+                ));
+            }
+            if single_slice
+                && self.get_encode_caps(
+                    encode_guid,
+                    NV_ENC_CAPS::NV_ENC_CAPS_SINGLE_SLICE_INTRA_REFRESH,
+                )? == 0
+            {
+                return Err(EncodeError::new(
+                    ErrorKind::UnsupportedParam,
+                    "The encoder does not support single slice intra refresh for this codec.",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the intra refresh settings from the codec config.
+///
+/// Returns `None` if intra refresh is disabled, otherwise whether single
+/// slice intra refresh is enabled.
+fn intra_refresh_config(encode_guid: GUID, config: &NV_ENC_CONFIG) -> Option<bool> {
+    let (enabled, single_slice) = if encode_guid == NV_ENC_CODEC_H264_GUID {
+        let h264_config = unsafe { config.encodeCodecConfig.h264Config };
+        (
+            h264_config.enableIntraRefresh(),
+            h264_config.singleSliceIntraRefresh(),
+        )
+    } else if encode_guid == NV_ENC_CODEC_HEVC_GUID {
+        let hevc_config = unsafe { config.encodeCodecConfig.hevcConfig };
+        (
+            hevc_config.enableIntraRefresh(),
+            hevc_config.singleSliceIntraRefresh(),
+        )
+    } else if encode_guid == NV_ENC_CODEC_AV1_GUID {
+        (
+            unsafe { config.encodeCodecConfig.av1Config }.enableIntraRefresh(),
+            0,
+        )
+    } else {
+        (0, 0)
+    };
+    (enabled != 0).then_some(single_slice != 0)
 }
 
 /// A safe wrapper for [`NV_ENC_INITIALIZE_PARAMS`], which is the encoder
@@ -541,4 +850,19 @@ impl<'a> EncoderInitParams<'a> {
         self.param.enablePTD = 1;
         self
     }
+
+    /// Specifies the number of state buffers to allocate for saving the
+    /// encoder state. Set this to a value greater than zero to be able to
+    /// re-encode frames using [`EncoderStateSlot`](super::EncoderStateSlot).
+    pub fn num_state_buffers(&mut self, num_state_buffers: u32) -> &mut Self {
+        self.param.numStateBuffers = num_state_buffers;
+        self
+    }
+
+    /// Enable output of the reconstructed frame, which can be received in a
+    /// [`ReconBuffer`](super::ReconBuffer).
+    pub fn enable_recon_frame_output(&mut self) -> &mut Self {
+        self.param.set_enableReconFrameOutput(1);
+        self
+    }
 }
@@ -0,0 +1,293 @@
+//! Defines [`PixelFormat`] which describes the memory layout of raw frames.
+//!
+//! The encoder and decoder APIs each have their own raw enum for pixel
+//! formats, [`NV_ENC_BUFFER_FORMAT`] and [`cudaVideoSurfaceFormat`]. Neither
+//! carries any information about the layout of a frame, so [`PixelFormat`]
+//! provides conversions to and from both, along with the plane layout and
+//! frame size of each format.
+
+use super::result::{EncodeError, ErrorKind};
+use crate::sys::{cuviddec::cudaVideoSurfaceFormat, nvEncodeAPI::NV_ENC_BUFFER_FORMAT};
+
+/// The pitch of device memory passed to the encoder must be a multiple of
+/// this many bytes.
+pub(crate) const PITCH_ALIGNMENT: u32 = 4;
+
+/// Chroma subsampling of a [`PixelFormat`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// The chroma planes have half the width and half the height of the luma
+    /// plane.
+    Yuv420,
+    /// The chroma planes have the same size as the luma plane. This is also
+    /// used for the packed formats, which are not subsampled.
+    Yuv444,
+}
+
+/// The memory layout of a raw frame.
+///
+/// Each format consists of one or more planes stored one after the other.
+/// Each row of the first plane starts [`PixelFormat::min_pitch`] or more
+/// bytes after the previous one, see [`PixelFormat::plane_pitch`] for the
+/// pitch of the other planes.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Semi-planar 8-bit YUV 4:2:0, with a Y plane followed by an
+    /// interleaved UV plane.
+    Nv12,
+    /// Planar 8-bit YUV 4:2:0, with a Y plane followed by V and U planes.
+    Yv12,
+    /// Planar 8-bit YUV 4:2:0, with a Y plane followed by U and V planes.
+    Iyuv,
+    /// Planar 8-bit YUV 4:4:4, with a Y plane followed by U and V planes.
+    Yuv444,
+    /// Semi-planar YUV 4:2:0 with 2 bytes per sample, with a Y plane
+    /// followed by an interleaved UV plane. The most significant bits hold the
+    /// sample, so this can hold both 10-bit (P010) and 16-bit (P016) samples.
+    P016,
+    /// Planar YUV 4:4:4 with 2 bytes per sample, with a Y plane followed by
+    /// U and V planes. The most significant bits hold the sample.
+    Yuv444_16Bit,
+    /// Packed 8-bit A8R8G8B8, word-ordered.
+    Argb,
+    /// Packed 10-bit A2R10G10B10, word-ordered.
+    Argb10,
+    /// Packed 8-bit A8Y8U8V8, word-ordered.
+    Ayuv,
+    /// Packed 8-bit A8B8G8R8, word-ordered.
+    Abgr,
+    /// Packed 10-bit A2B10G10R10, word-ordered.
+    Abgr10,
+}
+
+impl PixelFormat {
+    /// Getter for the number of planes.
+    #[must_use]
+    pub fn num_planes(self) -> usize {
+        match self {
+            Self::Nv12 | Self::P016 => 2,
+            Self::Yv12 | Self::Iyuv | Self::Yuv444 | Self::Yuv444_16Bit => 3,
+            Self::Argb | Self::Argb10 | Self::Ayuv | Self::Abgr | Self::Abgr10 => 1,
+        }
+    }
+
+    /// Getter for the number of bytes per pixel in the first plane.
+    #[must_use]
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            Self::Nv12 | Self::Yv12 | Self::Iyuv | Self::Yuv444 => 1,
+            Self::P016 | Self::Yuv444_16Bit => 2,
+            Self::Argb | Self::Argb10 | Self::Ayuv | Self::Abgr | Self::Abgr10 => 4,
+        }
+    }
+
+    /// Getter for the number of significant bits per sample.
+    ///
+    /// The formats with 2 bytes per sample report 10 bits, since that is what
+    /// the encoder reads from them.
+    #[must_use]
+    pub fn bit_depth(self) -> u32 {
+        match self {
+            Self::Nv12
+            | Self::Yv12
+            | Self::Iyuv
+            | Self::Yuv444
+            | Self::Argb
+            | Self::Ayuv
+            | Self::Abgr => 8,
+            Self::P016 | Self::Yuv444_16Bit | Self::Argb10 | Self::Abgr10 => 10,
+        }
+    }
+
+    /// Getter for the chroma subsampling.
+    #[must_use]
+    pub fn chroma_subsampling(self) -> ChromaSubsampling {
+        match self {
+            Self::Nv12 | Self::Yv12 | Self::Iyuv | Self::P016 => ChromaSubsampling::Yuv420,
+            _ => ChromaSubsampling::Yuv444,
+        }
+    }
+
+    /// Whether the format holds RGB rather than YUV pixels.
+    #[must_use]
+    pub fn is_rgb(self) -> bool {
+        matches!(self, Self::Argb | Self::Argb10 | Self::Abgr | Self::Abgr10)
+    }
+
+    /// Get the smallest pitch of the first plane for a frame of the given
+    /// width, which is the size of a row in bytes.
+    #[must_use]
+    pub fn min_pitch(self, width: u32) -> u32 {
+        width * self.bytes_per_pixel()
+    }
+
+    /// Get the pitch of the first plane for a frame of the given width,
+    /// rounded up so that it can be used for device memory registered with
+    /// the encoder.
+    #[must_use]
+    pub fn aligned_pitch(self, width: u32) -> u32 {
+        (self.min_pitch(width) + PITCH_ALIGNMENT - 1) / PITCH_ALIGNMENT * PITCH_ALIGNMENT
+    }
+
+    /// Get the pitch of the plane at index `plane` from the pitch of the first
+    /// plane.
+    ///
+    /// The chroma planes of [`PixelFormat::Yv12`] and [`PixelFormat::Iyuv`]
+    /// use half of the pitch, all other planes use the same pitch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `plane` is not smaller than [`PixelFormat::num_planes`].
+    #[must_use]
+    pub fn plane_pitch(self, plane: usize, pitch: u32) -> u32 {
+        assert!(plane < self.num_planes(), "Plane {plane} is out of range");
+        if plane > 0 && matches!(self, Self::Yv12 | Self::Iyuv) {
+            (pitch + 1) / 2
+        } else {
+            pitch
+        }
+    }
+
+    /// Get the size in bytes of a frame with the given width, height, and
+    /// pitch of the first plane.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use nvidia_video_codec_sdk::PixelFormat;
+    /// // The chroma plane of NV12 has half the height of the luma plane.
+    /// assert_eq!(PixelFormat::Nv12.frame_size(1920, 1080, 2048), 2048 * 1620);
+    /// ```
+    #[must_use]
+    pub fn frame_size(self, width: u32, height: u32, pitch: u32) -> usize {
+        self.plane_layouts(width, height)
+            .iter()
+            .map(|plane| plane.pitch(pitch as usize) * plane.rows)
+            .sum()
+    }
+
+    /// Get the layout of the planes of a frame in this format.
+    pub(crate) fn plane_layouts(self, width: u32, height: u32) -> Vec<PlaneLayout> {
+        let width = width as usize;
+        let height = height as usize;
+        let bytes = self.bytes_per_pixel() as usize;
+        let plane = |width, row_bytes, rows, half_pitch| PlaneLayout {
+            width,
+            row_bytes,
+            rows,
+            half_pitch,
+        };
+        match self {
+            Self::Nv12 | Self::P016 => vec![
+                plane(width, width * bytes, height, false),
+                plane((width + 1) / 2, width * bytes, (height + 1) / 2, false),
+            ],
+            Self::Yv12 | Self::Iyuv => vec![
+                plane(width, width, height, false),
+                plane((width + 1) / 2, (width + 1) / 2, (height + 1) / 2, true),
+                plane((width + 1) / 2, (width + 1) / 2, (height + 1) / 2, true),
+            ],
+            Self::Yuv444 | Self::Yuv444_16Bit => {
+                vec![plane(width, width * bytes, height, false); 3]
+            }
+            Self::Argb | Self::Argb10 | Self::Ayuv | Self::Abgr | Self::Abgr10 => {
+                vec![plane(width, width * bytes, height, false)]
+            }
+        }
+    }
+}
+
+impl From<PixelFormat> for NV_ENC_BUFFER_FORMAT {
+    fn from(value: PixelFormat) -> Self {
+        match value {
+            PixelFormat::Nv12 => Self::NV_ENC_BUFFER_FORMAT_NV12,
+            PixelFormat::Yv12 => Self::NV_ENC_BUFFER_FORMAT_YV12,
+            PixelFormat::Iyuv => Self::NV_ENC_BUFFER_FORMAT_IYUV,
+            PixelFormat::Yuv444 => Self::NV_ENC_BUFFER_FORMAT_YUV444,
+            PixelFormat::P016 => Self::NV_ENC_BUFFER_FORMAT_YUV420_10BIT,
+            PixelFormat::Yuv444_16Bit => Self::NV_ENC_BUFFER_FORMAT_YUV444_10BIT,
+            PixelFormat::Argb => Self::NV_ENC_BUFFER_FORMAT_ARGB,
+            PixelFormat::Argb10 => Self::NV_ENC_BUFFER_FORMAT_ARGB10,
+            PixelFormat::Ayuv => Self::NV_ENC_BUFFER_FORMAT_AYUV,
+            PixelFormat::Abgr => Self::NV_ENC_BUFFER_FORMAT_ABGR,
+            PixelFormat::Abgr10 => Self::NV_ENC_BUFFER_FORMAT_ABGR10,
+        }
+    }
+}
+
+impl TryFrom<NV_ENC_BUFFER_FORMAT> for PixelFormat {
+    type Error = EncodeError;
+
+    fn try_from(value: NV_ENC_BUFFER_FORMAT) -> Result<Self, Self::Error> {
+        match value {
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_NV12 => Ok(Self::Nv12),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_YV12 => Ok(Self::Yv12),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_IYUV => Ok(Self::Iyuv),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_YUV444 => Ok(Self::Yuv444),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_YUV420_10BIT => Ok(Self::P016),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_YUV444_10BIT => Ok(Self::Yuv444_16Bit),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ARGB => Ok(Self::Argb),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ARGB10 => Ok(Self::Argb10),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_AYUV => Ok(Self::Ayuv),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ABGR => Ok(Self::Abgr),
+            NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ABGR10 => Ok(Self::Abgr10),
+            _ => Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!("The buffer format {value:?} does not describe a frame."),
+            )),
+        }
+    }
+}
+
+impl From<cudaVideoSurfaceFormat> for PixelFormat {
+    fn from(value: cudaVideoSurfaceFormat) -> Self {
+        match value {
+            cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_NV12 => Self::Nv12,
+            cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_P016 => Self::P016,
+            cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_YUV444 => Self::Yuv444,
+            cudaVideoSurfaceFormat::cudaVideoSurfaceFormat_YUV444_16Bit => Self::Yuv444_16Bit,
+        }
+    }
+}
+
+impl TryFrom<PixelFormat> for cudaVideoSurfaceFormat {
+    type Error = EncodeError;
+
+    fn try_from(value: PixelFormat) -> Result<Self, Self::Error> {
+        match value {
+            PixelFormat::Nv12 => Ok(Self::cudaVideoSurfaceFormat_NV12),
+            PixelFormat::P016 => Ok(Self::cudaVideoSurfaceFormat_P016),
+            PixelFormat::Yuv444 => Ok(Self::cudaVideoSurfaceFormat_YUV444),
+            PixelFormat::Yuv444_16Bit => Ok(Self::cudaVideoSurfaceFormat_YUV444_16Bit),
+            _ => Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!("The decoder cannot output frames in the {value:?} format."),
+            )),
+        }
+    }
+}
+
+/// The layout of a single plane of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PlaneLayout {
+    /// Number of samples in each row.
+    pub(crate) width: usize,
+    /// Number of bytes of pixel data in each row.
+    pub(crate) row_bytes: usize,
+    /// Number of rows.
+    pub(crate) rows: usize,
+    /// Whether the pitch of this plane is half of the pitch of the first
+    /// plane.
+    pub(crate) half_pitch: bool,
+}
+
+impl PlaneLayout {
+    /// Get the pitch of this plane from the pitch of the first plane.
+    pub(crate) fn pitch(&self, pitch: usize) -> usize {
+        if self.half_pitch {
+            (pitch + 1) / 2
+        } else {
+            pitch
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Defines [`Frame`] which describes a raw frame in host memory, and
+//! [`PlanesMut`] which gives access to the planes of a locked input buffer.
+//!
+//! A [`Frame`] can be written into an input buffer using
+//! [`BufferLock::write_frame`](super::BufferLock::write_frame), which takes
+//! care of the plane layout of the buffer format and of the pitch of the
+//! input buffer. Alternatively the frame can be written straight into the
+//! planes returned by
+//! [`BufferLock::planes_mut`](super::BufferLock::planes_mut).
+
+use super::{
+    format::PixelFormat,
+    result::{EncodeError, ErrorKind},
+};
+
+/// A raw frame in host memory.
+///
+/// The planes of the frame are stored one after the other in `data`, in the
+/// order given by the [`PixelFormat`]. For example a [`PixelFormat::Nv12`]
+/// frame consists of the luma plane followed by the interleaved chroma plane
+/// of half the height.
+///
+/// Each row of the luma plane starts [`Frame::pitch`] bytes after the
+/// previous one. See [`PixelFormat::plane_pitch`] for the pitch of the other
+/// planes.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+}
+
+impl<'a> Frame<'a> {
+    /// Create a [`Frame`] from tightly packed data, where the pitch is equal
+    /// to the width of the luma plane in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// length of `data` does not match the size of the frame.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use nvidia_video_codec_sdk::{Frame, PixelFormat};
+    /// // A 4x2 NV12 frame has 8 bytes of luma and 4 bytes of chroma.
+    /// let data = [0; 12];
+    /// let frame = Frame::new(PixelFormat::Nv12, 4, 2, &data).unwrap();
+    /// assert_eq!(frame.pitch(), 4);
+    ///
+    /// // Too little data is an error.
+    /// assert!(Frame::new(PixelFormat::Nv12, 4, 2, &data[..8]).is_err());
+    /// ```
+    pub fn new(
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+        data: &'a [u8],
+    ) -> Result<Self, EncodeError> {
+        Self::with_pitch(format, width, height, format.min_pitch(width), data)
+    }
+
+    /// Create a [`Frame`] where each row of the luma plane is `pitch` bytes
+    /// long.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// `pitch` is smaller than a row of the luma plane, or if the length of
+    /// `data` does not match the size of the frame.
+    pub fn with_pitch(
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+        pitch: u32,
+        data: &'a [u8],
+    ) -> Result<Self, EncodeError> {
+        let min_pitch = format.min_pitch(width);
+        if pitch < min_pitch {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!("The pitch {pitch} is smaller than a row of {min_pitch} bytes."),
+            ));
+        }
+        let size = format.frame_size(width, height, pitch);
+        if data.len() != size {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "A {width}x{height} frame in the {format:?} format with pitch {pitch} is \
+                     {size} bytes, but {} bytes were provided.",
+                    data.len()
+                ),
+            ));
+        }
+        Ok(Self {
+            data,
+            width,
+            height,
+            pitch,
+            format,
+        })
+    }
+
+    /// Getter for the data of the frame, including all planes.
+    #[must_use]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Getter for the width of the frame in pixels.
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Getter for the height of the frame in pixels.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Getter for the pitch (AKA stride) of the luma plane in bytes.
+    #[must_use]
+    pub fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    /// Getter for the pixel format of the frame.
+    #[must_use]
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+}
+
+/// Mutable views of the planes of a locked input buffer.
+///
+/// This type is created via
+/// [`BufferLock::planes_mut`](super::BufferLock::planes_mut). The variant
+/// depends on the pixel format of the session.
+#[derive(Debug)]
+pub enum PlanesMut<'a> {
+    /// A luma plane followed by an interleaved chroma plane of half the
+    /// width and height, used by [`PixelFormat::Nv12`] and
+    /// [`PixelFormat::P016`].
+    SemiPlanar {
+        /// The luma (Y) plane.
+        luma: PlaneMut<'a>,
+        /// The interleaved chroma (UV) plane.
+        chroma: PlaneMut<'a>,
+    },
+    /// Separate luma and chroma planes, used by [`PixelFormat::Yv12`],
+    /// [`PixelFormat::Iyuv`], [`PixelFormat::Yuv444`] and
+    /// [`PixelFormat::Yuv444_16Bit`].
+    ///
+    /// The chroma planes have half the width and height for the 4:2:0
+    /// formats. They are always named by their content, regardless of their
+    /// order in memory.
+    Planar {
+        /// The luma (Y) plane.
+        y: PlaneMut<'a>,
+        /// The blue-difference chroma (U) plane.
+        u: PlaneMut<'a>,
+        /// The red-difference chroma (V) plane.
+        v: PlaneMut<'a>,
+    },
+    /// A single plane of packed 32-bit pixels, used by the RGB formats and
+    /// [`PixelFormat::Ayuv`].
+    Packed(PlaneMut<'a>),
+}
+
+impl<'a> PlanesMut<'a> {
+    /// Split `data` into the planes of the pixel format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is smaller than the frame.
+    pub(crate) fn new(
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+        luma_pitch: usize,
+        data: &'a mut [u8],
+    ) -> Self {
+        let mut data = data;
+        let mut planes = format
+            .plane_layouts(width, height)
+            .into_iter()
+            .map(|layout| {
+                let pitch = layout.pitch(luma_pitch);
+                let (plane, rest) = std::mem::take(&mut data).split_at_mut(pitch * layout.rows);
+                data = rest;
+                PlaneMut {
+                    data: plane,
+                    width: layout.width,
+                    height: layout.rows,
+                    pitch,
+                    row_bytes: layout.row_bytes,
+                }
+            });
+        let mut next = || {
+            planes
+                .next()
+                .expect("The format should have enough planes.")
+        };
+        match format {
+            PixelFormat::Nv12 | PixelFormat::P016 => {
+                let luma = next();
+                let chroma = next();
+                Self::SemiPlanar { luma, chroma }
+            }
+            PixelFormat::Yv12 => {
+                let y = next();
+                let v = next();
+                let u = next();
+                Self::Planar { y, u, v }
+            }
+            PixelFormat::Iyuv | PixelFormat::Yuv444 | PixelFormat::Yuv444_16Bit => {
+                let y = next();
+                let u = next();
+                let v = next();
+                Self::Planar { y, u, v }
+            }
+            PixelFormat::Argb
+            | PixelFormat::Argb10
+            | PixelFormat::Ayuv
+            | PixelFormat::Abgr
+            | PixelFormat::Abgr10 => Self::Packed(next()),
+        }
+    }
+}
+
+/// A mutable view of a single plane of a locked input buffer.
+///
+/// Each row of the plane starts [`PlaneMut::pitch`] bytes after the previous
+/// one, which may be more than the size of the pixel data in the row.
+#[derive(Debug)]
+pub struct PlaneMut<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    pitch: usize,
+    row_bytes: usize,
+}
+
+impl PlaneMut<'_> {
+    /// Getter for the width of the plane in samples.
+    ///
+    /// For interleaved chroma planes this is the number of sample pairs, and
+    /// for packed planes the number of pixels.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Getter for the height of the plane in rows.
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Getter for the pitch (AKA stride) of the plane in bytes.
+    #[must_use]
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    /// Getter for the number of bytes of pixel data in each row.
+    #[must_use]
+    pub fn row_bytes(&self) -> usize {
+        self.row_bytes
+    }
+
+    /// Getter for the whole plane, including the padding at the end of each
+    /// row.
+    #[must_use]
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// Get the pixel data of the row at index `row`, without padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `row` is not smaller than the height of the plane.
+    #[must_use]
+    pub fn row_mut(&mut self, row: usize) -> &mut [u8] {
+        assert!(row < self.height, "Row {row} is out of range");
+        &mut self.data[row * self.pitch..][..self.row_bytes]
+    }
+
+    /// Iterate over the pixel data of each row, without padding.
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let row_bytes = self.row_bytes;
+        self.data
+            .chunks_mut(self.pitch)
+            .map(move |row| &mut row[..row_bytes])
+    }
+}
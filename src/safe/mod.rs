@@ -6,9 +6,25 @@
 mod api;
 mod buffer;
 mod builders;
+mod decode_pipeline;
+mod decoder;
+mod device;
 mod encoder;
+mod format;
+mod frame;
+mod parser;
+mod pipeline;
+mod pool;
+mod recon;
 mod result;
+mod sei;
 mod session;
+mod shared;
+mod state;
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(all(feature = "vulkan", unix))]
+mod vulkan;
 
 pub use api::{EncodeAPI, ENCODE_API};
 pub use buffer::{
@@ -16,10 +32,41 @@ pub use buffer::{
     BitstreamLock,
     Buffer,
     BufferLock,
+    EncodeStats,
     EncoderInput,
     EncoderOutput,
+    MappedResource,
     RegisteredResource,
 };
+pub use builders::{IntraRefresh, TemporalLayers};
+pub use decode_pipeline::{DecodePipeline, DecodedFrame};
+pub use decoder::{DecodeStatus, Decoder, DecoderCaps, DecoderInitParams, MappedFrame, Rect};
+#[cfg(target_os = "windows")]
+pub use device::DirectXDevice;
+#[cfg(target_os = "linux")]
+pub use device::OpenGlDevice;
+pub use device::{CudaDevice, EncodeDevice};
 pub use encoder::{Encoder, EncoderInitParams};
+pub use format::{ChromaSubsampling, PixelFormat};
+pub use frame::{Frame, PlaneMut, PlanesMut};
+pub use parser::{
+    Av1OperatingPoint,
+    Av1OperatingPointChoice,
+    DisplayInfo,
+    ParserHandler,
+    ParserInitParams,
+    VideoFormat,
+    VideoParser,
+};
+pub use pipeline::{EncodePipeline, EncodedPacket};
+pub use pool::BufferPool;
+pub use recon::{ReconBuffer, ReconLock};
 pub use result::{EncodeError, ErrorKind};
+pub use sei::{ContentLightLevel, MasteringDisplay, SeiMessage, TimeCode};
 pub use session::{CodecPictureParams, EncodePictureParams, Session};
+pub use shared::SharedSession;
+pub use state::EncoderStateSlot;
+#[cfg(feature = "async")]
+pub use stream::EncodeStream;
+#[cfg(all(feature = "vulkan", unix))]
+pub use vulkan::{VulkanMappedResource, VulkanMemory, VulkanSemaphore, VulkanSync};
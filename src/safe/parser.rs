@@ -0,0 +1,527 @@
+//! Defines [`VideoParser`], a safe wrapper around the NVDEC video parser.
+//!
+//! The parser splits an elementary stream into pictures and calls back into
+//! a [`ParserHandler`] when the sequence header changes, when a picture is
+//! ready to be decoded and when a picture is ready to be displayed.
+
+use std::{
+    any::Any,
+    ffi::{c_int, c_ulong, c_void},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    slice,
+};
+
+use super::{
+    decoder::Rect,
+    result::{EncodeError, ErrorKind},
+    sei::SeiMessage,
+};
+use crate::sys::{
+    cuviddec::{cudaVideoChromaFormat, cudaVideoCodec, CUVIDPICPARAMS},
+    nvcuvid::{
+        cuvidCreateVideoParser,
+        cuvidDestroyVideoParser,
+        cuvidParseVideoData,
+        CUvideopacketflags,
+        CUvideoparser,
+        CUVIDEOFORMAT,
+        CUVIDOPERATINGPOINTINFO,
+        CUVIDPARSERDISPINFO,
+        CUVIDPARSERPARAMS,
+        CUVIDSEIMESSAGEINFO,
+        CUVIDSOURCEDATAPACKET,
+    },
+};
+
+/// The unit of the timestamps if no clock rate is given.
+const DEFAULT_CLOCK_RATE: u32 = 10_000_000;
+
+/// The format of a video sequence, parsed from its sequence header.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct VideoFormat {
+    /// The codec of the stream.
+    pub codec: cudaVideoCodec,
+    /// The frame rate as a fraction `(numerator, denominator)`.
+    pub frame_rate: (u32, u32),
+    /// Whether the sequence only contains progressive frames.
+    pub progressive: bool,
+    /// The bit depth of the luma samples.
+    pub bit_depth: u32,
+    /// The chroma format of the coded pictures.
+    pub chroma_format: cudaVideoChromaFormat,
+    /// The minimum number of decode surfaces needed to decode the sequence.
+    pub min_num_decode_surfaces: u32,
+    /// The coded width of the pictures.
+    pub coded_width: u32,
+    /// The coded height of the pictures.
+    pub coded_height: u32,
+    /// The area of the coded pictures which should be displayed.
+    pub display_area: Rect,
+}
+
+impl From<&CUVIDEOFORMAT> for VideoFormat {
+    fn from(format: &CUVIDEOFORMAT) -> Self {
+        let edge = |value: c_int| u32::try_from(value).unwrap_or(0);
+        Self {
+            codec: format.codec,
+            frame_rate: (format.frame_rate.numerator, format.frame_rate.denominator),
+            progressive: format.progressive_sequence != 0,
+            bit_depth: u32::from(format.bit_depth_luma_minus8) + 8,
+            chroma_format: format.chroma_format,
+            min_num_decode_surfaces: format.min_num_decode_surfaces.into(),
+            coded_width: format.coded_width,
+            coded_height: format.coded_height,
+            display_area: Rect::new(
+                edge(format.display_area.left),
+                edge(format.display_area.top),
+                edge(format.display_area.right),
+                edge(format.display_area.bottom),
+            ),
+        }
+    }
+}
+
+/// Information about a decoded picture which is ready to be displayed.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// The index of the decode surface holding the picture.
+    pub picture_index: i32,
+    /// Whether the picture is a progressive frame rather than two fields.
+    pub progressive_frame: bool,
+    /// Whether the top field is displayed first.
+    pub top_field_first: bool,
+    /// The number of additional fields to display the first field for.
+    pub repeat_first_field: i32,
+    /// The timestamp passed to [`VideoParser::parse`] with the picture.
+    pub timestamp: i64,
+}
+
+impl From<&CUVIDPARSERDISPINFO> for DisplayInfo {
+    fn from(info: &CUVIDPARSERDISPINFO) -> Self {
+        Self {
+            picture_index: info.picture_index,
+            progressive_frame: info.progressive_frame != 0,
+            top_field_first: info.top_field_first != 0,
+            repeat_first_field: info.repeat_first_field,
+            timestamp: info.timestamp,
+        }
+    }
+}
+
+/// An operating point of a scalable AV1 stream, as listed in its sequence
+/// header.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Av1OperatingPoint {
+    /// The `operating_point_idc` of the operating point, a mask of the
+    /// temporal layers in bits 0 to 7 and of the spatial layers in bits 8 to
+    /// 11. It is 0 if the stream is not scalable.
+    pub idc: u16,
+}
+
+impl Av1OperatingPoint {
+    /// Get the mask of the temporal layers in the operating point, where bit
+    /// `i` is set if temporal layer `i` is included.
+    #[must_use]
+    pub fn temporal_layers(&self) -> u8 {
+        (self.idc & 0xff) as u8
+    }
+
+    /// Get the mask of the spatial layers in the operating point, where bit
+    /// `i` is set if spatial layer `i` is included.
+    #[must_use]
+    pub fn spatial_layers(&self) -> u8 {
+        ((self.idc >> 8) & 0xf) as u8
+    }
+}
+
+/// The operating point of an AV1 stream to decode, returned by
+/// [`ParserHandler::av1_operating_point`].
+///
+/// The default decodes operating point 0, which usually includes all layers,
+/// and only outputs the highest spatial layer.
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Av1OperatingPointChoice {
+    /// The index of the operating point in the list of the sequence header.
+    pub index: u32,
+    /// Whether to output the frames of all spatial layers of the operating
+    /// point, rather than only those of the highest one. The layers can have
+    /// different sizes.
+    pub output_all_layers: bool,
+}
+
+/// The callbacks of a [`VideoParser`].
+///
+/// Each callback returns whether it succeeded. If one fails, parsing stops
+/// and [`VideoParser::parse`] returns an error. If one panics, the panic is
+/// resumed by [`VideoParser::parse`] instead of unwinding through the parser.
+pub trait ParserHandler {
+    /// Called for the first sequence header and whenever the format of the
+    /// sequence changes, before any picture of the sequence is decoded.
+    ///
+    /// Returns the number of decode surfaces the decoder uses, which is
+    /// usually [`VideoFormat::min_num_decode_surfaces`], or `None` on
+    /// failure.
+    fn sequence(&mut self, format: &VideoFormat) -> Option<u32>;
+
+    /// Called in decode order when a picture is ready to be decoded, usually
+    /// with [`Decoder::decode_picture`](super::Decoder::decode_picture).
+    fn decode_picture(&mut self, picture: &mut CUVIDPICPARAMS) -> bool;
+
+    /// Called in display order when a decoded picture is ready to be
+    /// displayed, usually with
+    /// [`Decoder::map_frame`](super::Decoder::map_frame).
+    fn display_picture(&mut self, info: &DisplayInfo) -> bool;
+
+    /// Called for the sequence header of an AV1 stream, with the operating
+    /// points it contains.
+    ///
+    /// Returns the operating point to decode. Unlike the other callbacks this
+    /// cannot fail: returning `None`, or an index which is not in
+    /// `operating_points`, makes the parser decode operating point 0 without
+    /// all layers. The default implementation chooses
+    /// [`Av1OperatingPointChoice::default`].
+    fn av1_operating_point(
+        &mut self,
+        operating_points: &[Av1OperatingPoint],
+    ) -> Option<Av1OperatingPointChoice> {
+        let _ = operating_points;
+        Some(Av1OperatingPointChoice::default())
+    }
+
+    /// Called in decode order with the SEI messages of a picture, or the
+    /// metadata OBUs for AV1, before the picture is decoded.
+    ///
+    /// The messages belong to the picture which is later displayed with the
+    /// same [`DisplayInfo::picture_index`]. Returns whether the callback
+    /// succeeded. The default implementation ignores the messages.
+    fn sei_messages(&mut self, picture_index: i32, messages: Vec<SeiMessage>) -> bool {
+        let _ = (picture_index, messages);
+        true
+    }
+}
+
+/// The parameters for creating a [`VideoParser`], which fill in
+/// [`CUVIDPARSERPARAMS`].
+#[derive(Debug, Clone, Copy)]
+pub struct ParserInitParams {
+    codec: cudaVideoCodec,
+    clock_rate: u32,
+    error_threshold: u32,
+    max_display_delay: u32,
+}
+
+impl ParserInitParams {
+    /// Create a new builder for [`ParserInitParams`].
+    #[must_use]
+    pub fn new(codec: cudaVideoCodec) -> Self {
+        Self {
+            codec,
+            clock_rate: 0,
+            error_threshold: 0,
+            max_display_delay: 0,
+        }
+    }
+
+    /// Getter for the unit of the timestamps in Hz.
+    pub(crate) fn clock_rate_hz(&self) -> u32 {
+        if self.clock_rate == 0 {
+            DEFAULT_CLOCK_RATE
+        } else {
+            self.clock_rate
+        }
+    }
+
+    /// Specifies the unit of the timestamps in Hz. Defaults to 10 MHz.
+    pub fn clock_rate(&mut self, clock_rate: u32) -> &mut Self {
+        self.clock_rate = clock_rate;
+        self
+    }
+
+    /// Specifies the percentage of a picture, from 0 to 100, which may be
+    /// corrupted for it to still be passed to
+    /// [`ParserHandler::decode_picture`]. At 100 every picture is decoded,
+    /// however corrupted. Defaults to 0.
+    ///
+    /// Use [`Decoder::status`](super::Decoder::status) to find out whether a
+    /// picture was decoded with errors.
+    pub fn error_threshold(&mut self, error_threshold: u32) -> &mut Self {
+        self.error_threshold = error_threshold;
+        self
+    }
+
+    /// Specifies how many pictures the parser can hold back before displaying
+    /// them. A delay of 2 to 4 pictures lets the decoder work ahead, a delay
+    /// of 0 gives the lowest latency. Defaults to 0.
+    pub fn max_display_delay(&mut self, max_display_delay: u32) -> &mut Self {
+        self.max_display_delay = max_display_delay;
+        self
+    }
+}
+
+/// A parser for elementary video streams.
+///
+/// Chunks of the stream are passed to [`VideoParser::parse`], which calls the
+/// [`ParserHandler`] for the pictures found in them. The chunks do not have
+/// to be aligned to pictures, the parser keeps incomplete pictures until the
+/// next chunk.
+#[derive(Debug)]
+pub struct VideoParser<H: ParserHandler> {
+    ptr: CUvideoparser,
+    // Boxed so its address stays the same while the parser calls back into
+    // it.
+    user_data: *mut UserData<H>,
+}
+
+/// The state the callbacks of a [`VideoParser`] get.
+#[derive(Debug)]
+struct UserData<H> {
+    codec: cudaVideoCodec,
+    handler: H,
+    // A panic caught in a callback, resumed once the parser returns.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<H> UserData<H> {
+    /// Call the handler, returning `failed` instead of unwinding into the
+    /// parser if it panics.
+    fn call<R>(&mut self, failed: R, f: impl FnOnce(&mut H) -> R) -> R {
+        // Skip the remaining callbacks after a panic.
+        if self.panic.is_some() {
+            return failed;
+        }
+        let handler = &mut self.handler;
+        panic::catch_unwind(AssertUnwindSafe(|| f(handler))).unwrap_or_else(|payload| {
+            self.panic = Some(payload);
+            failed
+        })
+    }
+}
+
+// SAFETY: The parser is not tied to a thread, and only calls the handler from
+// `parse` and `flush`, which take `&mut self`.
+unsafe impl<H: ParserHandler + Send> Send for VideoParser<H> {}
+
+impl<H: ParserHandler> VideoParser<H> {
+    /// Create a parser which calls back into `handler`.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the codec is not supported or if we run out of memory.
+    pub fn new(params: &ParserInitParams, handler: H) -> Result<Self, EncodeError> {
+        let user_data = Box::into_raw(Box::new(UserData {
+            codec: params.codec,
+            handler,
+            panic: None,
+        }));
+        let mut parser_params = CUVIDPARSERPARAMS {
+            CodecType: params.codec,
+            // Overridden by the sequence callback.
+            ulMaxNumDecodeSurfaces: 1,
+            ulClockRate: params.clock_rate,
+            ulErrorThreshold: params.error_threshold,
+            ulMaxDisplayDelay: params.max_display_delay,
+            pUserData: user_data.cast(),
+            pfnSequenceCallback: Some(sequence_callback::<H>),
+            pfnDecodePicture: Some(decode_callback::<H>),
+            pfnDisplayPicture: Some(display_callback::<H>),
+            pfnGetOperatingPoint: Some(operating_point_callback::<H>),
+            pfnGetSEIMsg: Some(sei_callback::<H>),
+            ..Default::default()
+        };
+        let mut ptr = ptr::null_mut();
+        if let Err(err) = unsafe { cuvidCreateVideoParser(&mut ptr, &mut parser_params) }.result() {
+            drop(unsafe { Box::from_raw(user_data) });
+            return Err(err.into());
+        }
+        Ok(Self { ptr, user_data })
+    }
+
+    /// Getter for the handler.
+    #[must_use]
+    pub fn handler(&self) -> &H {
+        unsafe { &(*self.user_data).handler }
+    }
+
+    /// Mutable getter for the handler.
+    #[must_use]
+    pub fn handler_mut(&mut self) -> &mut H {
+        unsafe { &mut (*self.user_data).handler }
+    }
+
+    /// Parse a chunk of the stream, calling the handler for every complete
+    /// picture.
+    ///
+    /// The `timestamp` is passed on to
+    /// [`ParserHandler::display_picture`] with the picture which starts in
+    /// this chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the callbacks fails or if the stream is
+    /// invalid.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a callback, once the parser has returned.
+    pub fn parse(&mut self, data: &[u8], timestamp: i64) -> Result<(), EncodeError> {
+        let payload_size = c_ulong::try_from(data.len()).map_err(|_| {
+            EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!("A chunk of {} bytes is too large to parse.", data.len()),
+            )
+        })?;
+        self.parse_packet(CUVIDSOURCEDATAPACKET {
+            flags: CUvideopacketflags::CUVID_PKT_TIMESTAMP as c_ulong,
+            payload_size,
+            payload: data.as_ptr(),
+            timestamp,
+        })
+    }
+
+    /// Signal the end of the stream, so the handler is called for all
+    /// remaining pictures.
+    ///
+    /// The parser can be used for a new stream afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the callbacks fails.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a callback, once the parser has returned.
+    pub fn flush(&mut self) -> Result<(), EncodeError> {
+        self.parse_packet(CUVIDSOURCEDATAPACKET {
+            flags: CUvideopacketflags::CUVID_PKT_ENDOFSTREAM as c_ulong,
+            payload_size: 0,
+            payload: ptr::null(),
+            timestamp: 0,
+        })
+    }
+
+    fn parse_packet(&mut self, mut packet: CUVIDSOURCEDATAPACKET) -> Result<(), EncodeError> {
+        let result = unsafe { cuvidParseVideoData(self.ptr, &mut packet) }.result();
+        // A panic in a callback cannot unwind through the parser, so it is
+        // resumed here.
+        if let Some(payload) = unsafe { (*self.user_data).panic.take() } {
+            panic::resume_unwind(payload);
+        }
+        Ok(result?)
+    }
+}
+
+impl<H: ParserHandler> Drop for VideoParser<H> {
+    fn drop(&mut self) {
+        unsafe { cuvidDestroyVideoParser(self.ptr) }
+            .result()
+            .expect("The parser pointer should be valid.");
+        drop(unsafe { Box::from_raw(self.user_data) });
+    }
+}
+
+unsafe extern "C" fn sequence_callback<H: ParserHandler>(
+    user_data: *mut c_void,
+    format: *mut CUVIDEOFORMAT,
+) -> c_int {
+    let user_data = &mut *user_data.cast::<UserData<H>>();
+    let format = VideoFormat::from(&*format);
+    // 0 means failure, 1 keeps the number of surfaces the parser was created
+    // with, anything larger overrides it.
+    user_data.call(0, |handler| {
+        handler.sequence(&format).map_or(0, |surfaces| {
+            c_int::try_from(surfaces.max(1)).unwrap_or(c_int::MAX)
+        })
+    })
+}
+
+unsafe extern "C" fn decode_callback<H: ParserHandler>(
+    user_data: *mut c_void,
+    picture: *mut CUVIDPICPARAMS,
+) -> c_int {
+    let user_data = &mut *user_data.cast::<UserData<H>>();
+    let picture = &mut *picture;
+    user_data.call(0, |handler| handler.decode_picture(picture).into())
+}
+
+unsafe extern "C" fn display_callback<H: ParserHandler>(
+    user_data: *mut c_void,
+    info: *mut CUVIDPARSERDISPINFO,
+) -> c_int {
+    // The parser passes a null pointer at the end of the stream.
+    if info.is_null() {
+        return 1;
+    }
+    let user_data = &mut *user_data.cast::<UserData<H>>();
+    let info = DisplayInfo::from(&*info);
+    user_data.call(0, |handler| handler.display_picture(&info).into())
+}
+
+unsafe extern "C" fn operating_point_callback<H: ParserHandler>(
+    user_data: *mut c_void,
+    info: *mut CUVIDOPERATINGPOINTINFO,
+) -> c_int {
+    let user_data = &mut *user_data.cast::<UserData<H>>();
+    // Only AV1 streams have operating points.
+    let av1 = (*info).__bindgen_anon_1.av1;
+    let count = usize::from(av1.operating_points_cnt).min(av1.operating_points_idc.len());
+    let operating_points: Vec<_> = av1.operating_points_idc[..count]
+        .iter()
+        .map(|&idc| Av1OperatingPoint { idc })
+        .collect();
+    let Some(choice) = user_data.call(None, |handler| {
+        handler.av1_operating_point(&operating_points)
+    }) else {
+        return -1;
+    };
+    // Bits 0 to 9 hold the index and bit 10 whether to output all layers, a
+    // negative value selects the default operating point.
+    match c_int::try_from(choice.index) {
+        Ok(index) if (choice.index as usize) < count.max(1) => {
+            index | (c_int::from(choice.output_all_layers) << 10)
+        }
+        _ => -1,
+    }
+}
+
+unsafe extern "C" fn sei_callback<H: ParserHandler>(
+    user_data: *mut c_void,
+    info: *mut CUVIDSEIMESSAGEINFO,
+) -> c_int {
+    let user_data = &mut *user_data.cast::<UserData<H>>();
+    let info = &*info;
+    let Ok(picture_index) = i32::try_from(info.picIdx) else {
+        return 0;
+    };
+    let headers: &[_] = if info.pSEIMessage.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(info.pSEIMessage, info.sei_message_count as usize)
+    };
+    let total_size = headers
+        .iter()
+        .map(|header| header.sei_message_size as usize)
+        .sum();
+    let data: &[u8] = if info.pSEIData.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(info.pSEIData.cast(), total_size)
+    };
+    // The payloads of the messages follow each other in the data.
+    let mut offset = 0;
+    let messages = headers
+        .iter()
+        .filter_map(|header| {
+            let payload = data.get(offset..offset + header.sei_message_size as usize)?;
+            offset += payload.len();
+            Some(SeiMessage::parse(
+                user_data.codec,
+                header.sei_message_type,
+                payload,
+            ))
+        })
+        .collect();
+    user_data.call(0, |handler| {
+        handler.sei_messages(picture_index, messages).into()
+    })
+}
@@ -0,0 +1,387 @@
+//! Defines [`EncodePipeline`] which turns frames into encoded packets.
+//!
+//! The pipeline takes care of the details of driving the encoder: it retries
+//! while the encoder is busy, keeps frames in flight while the encoder needs
+//! more input, recycles input buffers and output bitstreams, and returns the
+//! encoded packets in decode order with presentation and decode timestamps.
+
+use std::{collections::VecDeque, mem, thread, time::Duration};
+
+use super::{
+    buffer::{Bitstream, Buffer, EncodeStats, EncoderInput},
+    device::{CudaDevice, EncodeDevice},
+    frame::Frame,
+    result::{EncodeError, ErrorKind},
+    session::{EncodePictureParams, Session},
+};
+use crate::sys::nvEncodeAPI::NV_ENC_PIC_TYPE;
+
+/// How often to retry while the encoder is busy, before giving up.
+const BUSY_RETRIES: u32 = 1000;
+/// How long to wait between retries while the encoder is busy.
+const BUSY_WAIT: Duration = Duration::from_millis(1);
+
+/// Functions for creating encode pipelines.
+impl<D: EncodeDevice> Session<D> {
+    /// Create an [`EncodePipeline`] which keeps at most `capacity` frames in
+    /// flight.
+    ///
+    /// The capacity must be larger than the number of frames the encoder
+    /// holds on to before producing output, which is the number of B frames
+    /// plus the lookahead depth. Buffers are only created when they are first
+    /// needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if
+    /// `capacity` is not larger than the number of B frames plus the
+    /// lookahead depth of the session.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     EncodePictureParams, Encoder, EncoderInitParams, Frame, PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// let data = vec![0; PixelFormat::Nv12.frame_size(WIDTH, HEIGHT, WIDTH)];
+    /// let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    ///
+    /// let mut pipeline = session.create_encode_pipeline(8).unwrap();
+    /// let mut packets = Vec::new();
+    /// for i in 0..32 {
+    ///     let params = EncodePictureParams {
+    ///         input_timestamp: i,
+    ///         ..Default::default()
+    ///     };
+    ///     packets.extend(pipeline.encode_frame(&frame, params).unwrap());
+    /// }
+    /// packets.extend(pipeline.finish().unwrap());
+    ///
+    /// assert_eq!(packets.len(), 32);
+    /// assert!(packets[0].keyframe);
+    /// ```
+    pub fn create_encode_pipeline(
+        &self,
+        capacity: usize,
+    ) -> Result<EncodePipeline<'_, D>, EncodeError> {
+        self.check_pipeline_capacity(capacity)?;
+        Ok(EncodePipeline {
+            session: self,
+            capacity,
+            free_inputs: Vec::new(),
+            free_outputs: Vec::new(),
+            in_flight: VecDeque::with_capacity(capacity),
+            timestamps: VecDeque::with_capacity(capacity),
+            dts_offset: None,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Check that a pipeline with `capacity` frames in flight has room for
+    /// one more frame than the encoder holds on to before producing output.
+    pub(crate) fn check_pipeline_capacity(&self, capacity: usize) -> Result<(), EncodeError> {
+        let lookahead = if self.rc_params.enableLookahead() == 0 {
+            0
+        } else {
+            usize::from(self.rc_params.lookaheadDepth)
+        };
+        let held = self.reorder_delay as usize + lookahead;
+        if capacity <= held {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "An encode pipeline must have room for more than the {held} frames the \
+                     encoder holds on to, but its capacity is {capacity}."
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An encoded frame, as returned by an [`EncodePipeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPacket {
+    /// The encoded bitstream of the frame.
+    pub data: Vec<u8>,
+    /// The presentation timestamp, which is the `input_timestamp` the frame
+    /// was submitted with.
+    pub pts: u64,
+    /// The decode timestamp.
+    ///
+    /// This is derived from the submitted timestamps, and is shifted back by
+    /// the reorder delay when B frames are used, so it can be negative for
+    /// the first packets.
+    pub dts: i64,
+    /// Whether the frame is an IDR frame, so decoding can start from it.
+    ///
+    /// Other I frames are not random access points, as later frames can
+    /// still reference frames before them.
+    pub keyframe: bool,
+    /// The statistics reported by the encoder.
+    pub stats: EncodeStats,
+}
+
+/// A frame which was submitted to the encoder, but whose output has not been
+/// read yet.
+#[derive(Debug)]
+struct InFlight<'a> {
+    // Only set if the frame was copied into an input buffer of the pipeline.
+    input: Option<Buffer<'a>>,
+    output: Bitstream<'a>,
+}
+
+/// A high-level interface for encoding frames into packets.
+///
+/// This type is created via [`Session::create_encode_pipeline`].
+/// Frames are submitted with [`EncodePipeline::encode_frame`] or
+/// [`EncodePipeline::encode_input`], which return the packets that have
+/// finished encoding. Once all frames are submitted,
+/// [`EncodePipeline::finish`] flushes the encoder and returns the remaining
+/// packets.
+///
+/// If reading the output of a frame fails, the packets read before it are
+/// returned by the next call instead.
+#[derive(Debug)]
+pub struct EncodePipeline<'a, D: EncodeDevice = CudaDevice> {
+    session: &'a Session<D>,
+    capacity: usize,
+    free_inputs: Vec<Buffer<'a>>,
+    free_outputs: Vec<Bitstream<'a>>,
+    // Submitted frames, in submission order.
+    in_flight: VecDeque<InFlight<'a>>,
+    // Submitted timestamps which have not been used as a decode timestamp.
+    timestamps: VecDeque<u64>,
+    // Set when the first packet is read.
+    dts_offset: Option<u64>,
+    // Packets which were read before an error, returned by the next call.
+    pending: Vec<EncodedPacket>,
+}
+
+impl<D: EncodeDevice> EncodePipeline<'_, D> {
+    /// Copy a frame from host memory into an input buffer and encode it.
+    ///
+    /// Returns the packets which have finished encoding, possibly none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if the
+    /// pipeline is full, because the capacity is too small for the number of
+    /// frames the encoder holds on to. Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if the
+    /// frame does not match the session, see
+    /// [`BufferLock::write_frame`](super::BufferLock::write_frame).
+    ///
+    /// Could also error if encoding fails, or if the encoder stays busy.
+    pub fn encode_frame(
+        &mut self,
+        frame: &Frame<'_>,
+        params: EncodePictureParams<'_>,
+    ) -> Result<Vec<EncodedPacket>, EncodeError> {
+        self.check_capacity()?;
+        let mut input = match self.free_inputs.pop() {
+            Some(input) => input,
+            None => self.session.create_input_buffer()?,
+        };
+        let written = input.lock().and_then(|mut lock| lock.write_frame(frame));
+        if let Err(err) = written {
+            self.free_inputs.push(input);
+            return Err(err);
+        }
+        let ready = match self.submit(&mut input, params) {
+            Ok(ready) => ready,
+            Err(err) => {
+                self.free_inputs.push(input);
+                return Err(err);
+            }
+        };
+        if let Some(frame) = self.in_flight.back_mut() {
+            frame.input = Some(input);
+        }
+        self.read_if_ready(ready)
+    }
+
+    /// Encode an input which is managed by the caller, such as a
+    /// [`MappedResource`](super::MappedResource).
+    ///
+    /// Returns the packets which have finished encoding, possibly none.
+    /// The input must stay valid, and mapped, until its packet has been
+    /// returned, since the encoder may still read from it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if the
+    /// pipeline is full, because the capacity is too small for the number of
+    /// frames the encoder holds on to.
+    ///
+    /// Could also error if encoding fails, or if the encoder stays busy.
+    pub fn encode_input<I: EncoderInput>(
+        &mut self,
+        input: &mut I,
+        params: EncodePictureParams<'_>,
+    ) -> Result<Vec<EncodedPacket>, EncodeError> {
+        self.check_capacity()?;
+        let ready = self.submit(input, params)?;
+        self.read_if_ready(ready)
+    }
+
+    /// Flush the encoder and return all remaining packets.
+    ///
+    /// See [`Session::end_of_stream`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if flushing fails, if the encoder stays busy, or if
+    /// reading the output fails.
+    pub fn finish(&mut self) -> Result<Vec<EncodedPacket>, EncodeError> {
+        retry_while_busy(|| self.session.end_of_stream())?;
+        self.read_packets(self.in_flight.len())
+    }
+
+    /// Getter for the number of frames which were submitted but whose packet
+    /// has not been returned yet.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn check_capacity(&self) -> Result<(), EncodeError> {
+        if self.in_flight.len() >= self.capacity {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                format!(
+                    "The encode pipeline is full with {} frames in flight, its capacity should be \
+                     larger than the number of B frames plus the lookahead depth.",
+                    self.capacity
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Submit a frame and put it in flight.
+    ///
+    /// Returns whether the output of all frames in flight is available.
+    fn submit<I: EncoderInput>(
+        &mut self,
+        input: &mut I,
+        params: EncodePictureParams<'_>,
+    ) -> Result<bool, EncodeError> {
+        let mut output = match self.free_outputs.pop() {
+            Some(output) => output,
+            None => self.session.create_output_bitstream()?,
+        };
+        let timestamp = params.input_timestamp;
+        let mut encode_pic_params = match self.session.pic_params(input, &mut output, params) {
+            Ok(encode_pic_params) => encode_pic_params,
+            Err(err) => {
+                self.free_outputs.push(output);
+                return Err(err);
+            }
+        };
+        let ready = match retry_while_busy(|| self.session.submit_picture(&mut encode_pic_params)) {
+            Ok(()) => true,
+            Err(err) if err.kind() == ErrorKind::NeedMoreInput => false,
+            Err(err) => {
+                self.free_outputs.push(output);
+                return Err(err);
+            }
+        };
+        self.in_flight.push_back(InFlight {
+            input: None,
+            output,
+        });
+        self.timestamps.push_back(timestamp);
+        Ok(ready)
+    }
+
+    fn read_if_ready(&mut self, ready: bool) -> Result<Vec<EncodedPacket>, EncodeError> {
+        self.read_packets(if ready { self.in_flight.len() } else { 0 })
+    }
+
+    /// Read the packets of the `count` oldest frames in flight after any
+    /// pending packets, and recycle their buffers.
+    fn read_packets(&mut self, count: usize) -> Result<Vec<EncodedPacket>, EncodeError> {
+        let mut packets = mem::take(&mut self.pending);
+        packets.reserve(count);
+        for _ in 0..count {
+            let Some(mut frame) = self.in_flight.pop_front() else {
+                break;
+            };
+            let dts = self.next_dts();
+            let packet = frame.output.lock().map(|lock| {
+                let stats = lock.stats();
+                EncodedPacket {
+                    data: lock.data().to_vec(),
+                    pts: lock.timestamp(),
+                    dts,
+                    keyframe: stats.picture_type == NV_ENC_PIC_TYPE::NV_ENC_PIC_TYPE_IDR,
+                    stats,
+                }
+            });
+            self.free_inputs.extend(frame.input);
+            self.free_outputs.push(frame.output);
+            match packet {
+                Ok(packet) => packets.push(packet),
+                Err(err) => {
+                    self.pending = packets;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(packets)
+    }
+
+    /// Get the decode timestamp of the next packet.
+    ///
+    /// Packets are returned in decode order, so the decode timestamps are the
+    /// submitted timestamps in submission order. They are shifted back by
+    /// the time between the first frame and the first frame after the
+    /// reorder delay, so that the decode timestamp never exceeds the
+    /// presentation timestamp.
+    // Timestamps are far from overflowing an `i64`.
+    #[allow(clippy::cast_possible_wrap)]
+    fn next_dts(&mut self) -> i64 {
+        let dts_offset = *self.dts_offset.get_or_insert_with(|| {
+            let delay = (self.session.reorder_delay as usize).min(self.timestamps.len() - 1);
+            self.timestamps[delay].saturating_sub(self.timestamps[0])
+        });
+        let timestamp = self
+            .timestamps
+            .pop_front()
+            .expect("Each frame in flight should have a timestamp.");
+        timestamp as i64 - dts_offset as i64
+    }
+}
+
+/// Call `f` until the encoder is no longer busy.
+fn retry_while_busy(mut f: impl FnMut() -> Result<(), EncodeError>) -> Result<(), EncodeError> {
+    let mut retries = 0;
+    loop {
+        match f() {
+            Err(err) if err.kind() == ErrorKind::EncoderBusy && retries < BUSY_RETRIES => {
+                retries += 1;
+                thread::sleep(BUSY_WAIT);
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Defines [`BufferPool`] which recycles input buffers and output bitstreams.
+//!
+//! The encoder may hold on to several frames before it produces output, for
+//! example when using B frames or lookahead. The [`BufferPool`] keeps track
+//! of the frames in flight and hands out the encoded output in submission
+//! order, so the buffers can be reused once their output has been read.
+
+use std::collections::VecDeque;
+
+use super::{
+    buffer::{Bitstream, BitstreamLock, Buffer},
+    device::{CudaDevice, EncodeDevice},
+    result::{EncodeError, ErrorKind},
+    session::{EncodePictureParams, Session},
+};
+
+/// Functions for creating buffer pools.
+impl<D: EncodeDevice> Session<D> {
+    /// Create a [`BufferPool`] with `size` input buffers and output
+    /// bitstreams.
+    ///
+    /// The pool should be larger than the number of frames the encoder holds
+    /// on to before producing output, which is the number of B frames plus
+    /// the lookahead depth, so that there is always a free input buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if `size`
+    /// is 0.
+    ///
+    /// Could also error if creating the buffers fails, see
+    /// [`Session::create_input_buffer`] and
+    /// [`Session::create_output_bitstream`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     EncodePictureParams, Encoder, EncoderInitParams, Frame, PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// let data = vec![0; PixelFormat::Nv12.frame_size(WIDTH, HEIGHT, WIDTH)];
+    /// let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    ///
+    /// let mut pool = session.create_buffer_pool(8).unwrap();
+    /// let mut encoded = Vec::new();
+    /// for _ in 0..32 {
+    ///     // Read out finished frames until an input buffer is free.
+    ///     while pool.input().is_none() {
+    ///         let lock = pool.next_output().unwrap().unwrap();
+    ///         encoded.extend_from_slice(lock.data());
+    ///     }
+    ///     let input = pool.input().unwrap();
+    ///     input.lock().unwrap().write_frame(&frame).unwrap();
+    ///     pool.submit(EncodePictureParams::default()).unwrap();
+    /// }
+    ///
+    /// // Flush the encoder and read out the remaining frames.
+    /// pool.finish().unwrap();
+    /// while let Some(lock) = pool.next_output() {
+    ///     encoded.extend_from_slice(lock.unwrap().data());
+    /// }
+    /// ```
+    pub fn create_buffer_pool(&self, size: usize) -> Result<BufferPool<'_, D>, EncodeError> {
+        if size == 0 {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                "A buffer pool must have at least one buffer.",
+            ));
+        }
+        let free = (0..size)
+            .map(|_| Ok((self.create_input_buffer()?, self.create_output_bitstream()?)))
+            .collect::<Result<Vec<_>, EncodeError>>()?;
+        Ok(BufferPool {
+            session: self,
+            free,
+            in_flight: VecDeque::with_capacity(size),
+            ready: 0,
+            reading: None,
+        })
+    }
+}
+
+/// A pool of input buffers and output bitstreams which are recycled
+/// automatically.
+///
+/// This type is created via [`Session::create_buffer_pool`].
+/// Frames are written into the buffer returned by [`BufferPool::input`] and
+/// submitted with [`BufferPool::submit`]. The encoded frames are then read
+/// in submission order using [`BufferPool::next_output`].
+#[derive(Debug)]
+pub struct BufferPool<'a, D: EncodeDevice = CudaDevice> {
+    session: &'a Session<D>,
+    free: Vec<(Buffer<'a>, Bitstream<'a>)>,
+    // Submitted frames, oldest first.
+    in_flight: VecDeque<(Buffer<'a>, Bitstream<'a>)>,
+    // The number of frames at the front of `in_flight` with finished output.
+    ready: usize,
+    // The frame whose output was returned last, recycled on the next call.
+    reading: Option<(Buffer<'a>, Bitstream<'a>)>,
+}
+
+impl<'a, D: EncodeDevice> BufferPool<'a, D> {
+    /// Get the input buffer which will be used by the next
+    /// [`BufferPool::submit`].
+    ///
+    /// Returns `None` if all buffers are in flight. In that case read out
+    /// finished frames with [`BufferPool::next_output`] first.
+    pub fn input(&mut self) -> Option<&mut Buffer<'a>> {
+        self.recycle();
+        self.free.last_mut().map(|(input, _)| input)
+    }
+
+    /// Encode the frame in the buffer returned by [`BufferPool::input`].
+    ///
+    /// If the encoder needs more input before producing output, the frame
+    /// stays in flight and this still returns `Ok(())`.
+    ///
+    /// See [`Session::encode_picture`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if all
+    /// buffers are in flight.
+    ///
+    /// Could also error if encoding fails. If this returns an error with
+    /// [`ErrorKind::EncoderBusy`](super::ErrorKind::EncoderBusy) then you
+    /// should retry after a few milliseconds. The frame is not submitted on
+    /// error, so the same input buffer is returned again by
+    /// [`BufferPool::input`].
+    pub fn submit(&mut self, params: EncodePictureParams<'_>) -> Result<(), EncodeError> {
+        self.recycle();
+        let Some(mut frame) = self.free.pop() else {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "All buffers of the pool are in flight.",
+            ));
+        };
+        let (input, output) = &mut frame;
+        match self.session.encode_picture(input, output, params) {
+            Ok(()) => {
+                self.in_flight.push_back(frame);
+                // All submitted frames have their output available.
+                self.ready = self.in_flight.len();
+            }
+            Err(err) if err.kind() == ErrorKind::NeedMoreInput => {
+                self.in_flight.push_back(frame);
+            }
+            Err(err) => {
+                self.free.push(frame);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the encoder, so that the output of all frames in flight
+    /// becomes available.
+    ///
+    /// See [`Session::end_of_stream`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if flushing fails. If this returns an error with
+    /// [`ErrorKind::EncoderBusy`](super::ErrorKind::EncoderBusy) then you
+    /// should retry after a few milliseconds.
+    pub fn finish(&mut self) -> Result<(), EncodeError> {
+        self.session.end_of_stream()?;
+        self.ready = self.in_flight.len();
+        Ok(())
+    }
+
+    /// Lock the output bitstream of the oldest finished frame.
+    ///
+    /// Returns `None` if no frame has finished encoding. The buffers of the
+    /// frame are recycled once the lock is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Could error if locking the bitstream fails, see [`Bitstream::lock`].
+    pub fn next_output(&mut self) -> Option<Result<BitstreamLock<'_, '_>, EncodeError>> {
+        self.recycle();
+        if self.ready == 0 {
+            return None;
+        }
+        self.ready -= 1;
+        let frame = self.in_flight.pop_front()?;
+        let (_, output) = self.reading.insert(frame);
+        Some(output.lock())
+    }
+
+    /// Getter for the number of frames which were submitted but whose output
+    /// has not been read yet.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Put the frame whose output was read last back into the free buffers.
+    fn recycle(&mut self) {
+        if let Some(frame) = self.reading.take() {
+            self.free.push(frame);
+        }
+    }
+}
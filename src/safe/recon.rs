@@ -0,0 +1,265 @@
+//! Defines [`ReconBuffer`] which receives the reconstructed frame from the
+//! encoder.
+//!
+//! The reconstructed frame is the picture as a decoder would see it after
+//! decoding the encoded bitstream. Reading it back allows measuring the
+//! encoding quality (for example PSNR or SSIM) without decoding the output.
+
+use std::{ffi::c_void, ptr};
+
+use cudarc::driver::{CudaSlice, DevicePtr};
+
+use super::{
+    api::ENCODE_API,
+    device::CudaDevice,
+    encoder::RawEncoder,
+    format::PixelFormat,
+    result::{EncodeError, ErrorKind},
+    session::Session,
+};
+use crate::sys::nvEncodeAPI::{
+    NV_ENC_BUFFER_FORMAT,
+    NV_ENC_BUFFER_USAGE,
+    NV_ENC_CAPS,
+    NV_ENC_INPUT_RESOURCE_TYPE,
+    NV_ENC_MAP_INPUT_RESOURCE,
+    NV_ENC_MAP_INPUT_RESOURCE_VER,
+    NV_ENC_REGISTER_RESOURCE,
+};
+
+/// Functions for creating reconstructed frame buffers.
+impl Session<CudaDevice> {
+    /// Create a [`ReconBuffer`].
+    ///
+    /// The buffer is allocated in device memory and registered with the
+    /// encoder. Attach it to an encode using
+    /// [`EncodePictureParams::recon_buffer`](super::EncodePictureParams::recon_buffer)
+    /// and read the reconstructed frame using [`ReconBuffer::lock`].
+    ///
+    /// The reconstructed frame is in the
+    /// [`NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_NV12`] format, or in the
+    /// [`NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_YUV420_10BIT`] format if
+    /// the session uses a 10-bit input format.
+    ///
+    /// Reconstructed frame output has to be enabled when starting the session
+    /// using
+    /// [`EncoderInitParams::enable_recon_frame_output`](super::EncoderInitParams::enable_recon_frame_output).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if
+    /// reconstructed frame output was not enabled for the session, and an
+    /// error with
+    /// [`ErrorKind::UnsupportedParam`](super::ErrorKind::UnsupportedParam) if
+    /// the encoder does not support it for the session codec.
+    ///
+    /// Could also error if registration or mapping fails, or if we run out of
+    /// memory.
+    pub fn create_recon_buffer(&self) -> Result<ReconBuffer<'_>, EncodeError> {
+        if !self.recon_frame_output {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "Reconstructed frame output was not enabled for the session.",
+            ));
+        }
+        let recon_supported = self.encoder.get_encode_caps(
+            self.encode_guid,
+            NV_ENC_CAPS::NV_ENC_CAPS_OUTPUT_RECON_SURFACE,
+        )?;
+        if recon_supported == 0 {
+            return Err(EncodeError::new(
+                ErrorKind::UnsupportedParam,
+                "The encoder does not support reconstructed frame output for this codec.",
+            ));
+        }
+
+        let format = match PixelFormat::try_from(self.buffer_format) {
+            Ok(format) if format.bit_depth() > 8 => PixelFormat::P016,
+            _ => PixelFormat::Nv12,
+        };
+        let buffer_format = format.into();
+        let pitch = format.aligned_pitch(self.width);
+        let size = format.frame_size(self.width, self.height, pitch);
+        let stream = self.encoder.cuda_ctx().default_stream();
+        let memory = stream.alloc_zeros::<u8>(size)?;
+
+        // Register resource.
+        let (device_ptr, _) = memory.device_ptr(&stream);
+        let mut register_resource_params = NV_ENC_REGISTER_RESOURCE::new(
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
+            self.width,
+            self.height,
+            device_ptr as *mut c_void,
+            buffer_format,
+        )
+        .pitch(pitch)
+        .buffer_usage(NV_ENC_BUFFER_USAGE::NV_ENC_OUTPUT_RECON);
+        unsafe {
+            (ENCODE_API.register_resource)(self.encoder.raw.ptr, &mut register_resource_params)
+        }
+        .result(&self.encoder)?;
+
+        let mut recon_buffer = ReconBuffer {
+            reg_ptr: register_resource_params.registeredResource,
+            map_ptr: ptr::null_mut(),
+            width: self.width,
+            height: self.height,
+            pitch,
+            buffer_format,
+            memory,
+            encoder: &self.encoder.raw,
+        };
+        recon_buffer.map()?;
+        Ok(recon_buffer)
+    }
+}
+
+/// Abstraction around a device memory buffer which the encoder writes the
+/// reconstructed frame into.
+///
+/// This type is created via [`Session::create_recon_buffer`].
+/// The buffer is automatically unmapped, unregistered, and freed when dropped.
+#[derive(Debug)]
+pub struct ReconBuffer<'a> {
+    reg_ptr: *mut c_void,
+    map_ptr: *mut c_void,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    buffer_format: NV_ENC_BUFFER_FORMAT,
+    memory: CudaSlice<u8>,
+    encoder: &'a RawEncoder,
+}
+
+unsafe impl Send for ReconBuffer<'_> {}
+
+impl<'a> ReconBuffer<'a> {
+    /// Getter for the buffer format of the reconstructed frame.
+    #[must_use]
+    pub fn buffer_format(&self) -> NV_ENC_BUFFER_FORMAT {
+        self.buffer_format
+    }
+
+    /// Get the handle which is passed to the encoder.
+    pub(crate) fn handle(&self) -> *mut c_void {
+        self.map_ptr
+    }
+
+    /// Lock the reconstructed frame and copy it to host memory.
+    ///
+    /// The buffer is unmapped from the encoder while it is locked, which
+    /// waits for the encoder to finish writing. The reconstructed frame is
+    /// only valid after the encode it was attached to has completed, so you
+    /// should lock the output bitstream of that encode first.
+    ///
+    /// On drop, [`ReconLock`] will map the buffer again so it can be reused.
+    ///
+    /// # Errors
+    ///
+    /// Could error if unmapping fails or if copying the frame fails.
+    pub fn lock<'b>(&'b mut self) -> Result<ReconLock<'b, 'a>, EncodeError> {
+        unsafe { (ENCODE_API.unmap_input_resource)(self.encoder.ptr, self.map_ptr) }
+            .result_raw(self.encoder)?;
+        self.map_ptr = ptr::null_mut();
+        // Create the lock before copying, so the buffer gets mapped again
+        // even if the copy fails.
+        let mut lock = ReconLock {
+            buffer: self,
+            data: Vec::new(),
+        };
+        let stream = lock.buffer.memory.stream().clone();
+        lock.data = stream.memcpy_dtov(&lock.buffer.memory)?;
+        stream.synchronize()?;
+        Ok(lock)
+    }
+
+    fn map(&mut self) -> Result<(), EncodeError> {
+        let mut map_input_resource_params = NV_ENC_MAP_INPUT_RESOURCE {
+            version: NV_ENC_MAP_INPUT_RESOURCE_VER,
+            registeredResource: self.reg_ptr,
+            mappedResource: ptr::null_mut(),
+            mappedBufferFmt: NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_UNDEFINED,
+            ..Default::default()
+        };
+        unsafe {
+            (ENCODE_API.map_input_resource)(self.encoder.ptr, &mut map_input_resource_params)
+        }
+        .result_raw(self.encoder)?;
+        self.map_ptr = map_input_resource_params.mappedResource;
+        Ok(())
+    }
+}
+
+/// Automatically unmap and unregister the buffer
+/// when it goes out of scope.
+impl Drop for ReconBuffer<'_> {
+    fn drop(&mut self) {
+        if !self.map_ptr.is_null() {
+            unsafe { (ENCODE_API.unmap_input_resource)(self.encoder.ptr, self.map_ptr) }
+                .result_raw(self.encoder)
+                .expect("The encoder pointer and map handle should be valid.");
+        }
+        unsafe { (ENCODE_API.unregister_resource)(self.encoder.ptr, self.reg_ptr) }
+            .result_raw(self.encoder)
+            .expect("The encoder pointer and resource handle should be valid.");
+    }
+}
+
+/// An RAII lock on the reconstructed frame buffer.
+///
+/// This type is created via [`ReconBuffer::lock`].
+/// It holds a host copy of the reconstructed frame, which consists of a luma
+/// plane followed by an interleaved chroma plane of half the height. Each row
+/// is [`ReconLock::pitch`] bytes long, which may be more than the width.
+#[derive(Debug)]
+pub struct ReconLock<'a, 'b> {
+    buffer: &'a mut ReconBuffer<'b>,
+    data: Vec<u8>,
+}
+
+impl ReconLock<'_, '_> {
+    /// Getter for the width of the frame in pixels.
+    #[must_use]
+    pub fn width(&self) -> u32 {
+        self.buffer.width
+    }
+
+    /// Getter for the height of the frame in pixels.
+    #[must_use]
+    pub fn height(&self) -> u32 {
+        self.buffer.height
+    }
+
+    /// Getter for the pitch (AKA stride) of each plane in bytes.
+    #[must_use]
+    pub fn pitch(&self) -> u32 {
+        self.buffer.pitch
+    }
+
+    /// Getter for the whole frame, including both planes.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Getter for the luma (Y) plane.
+    #[must_use]
+    pub fn luma(&self) -> &[u8] {
+        &self.data[..self.buffer.pitch as usize * self.buffer.height as usize]
+    }
+
+    /// Getter for the interleaved chroma (UV) plane.
+    #[must_use]
+    pub fn chroma(&self) -> &[u8] {
+        &self.data[self.buffer.pitch as usize * self.buffer.height as usize..]
+    }
+}
+
+impl Drop for ReconLock<'_, '_> {
+    fn drop(&mut self) {
+        self.buffer
+            .map()
+            .expect("The encoder pointer and resource handle should be valid.");
+    }
+}
@@ -4,7 +4,13 @@
 
 use std::{error::Error, ffi::CStr, fmt};
 
-use super::{api::ENCODE_API, encoder::Encoder};
+use cudarc::driver::{sys::CUresult, DriverError};
+
+use super::{
+    api::ENCODE_API,
+    device::EncodeDevice,
+    encoder::{Encoder, RawEncoder},
+};
 use crate::sys::nvEncodeAPI::NVENCSTATUS;
 
 /// Wrapper enum around [`NVENCSTATUS`].
@@ -112,6 +118,9 @@ pub enum ErrorKind {
 ///
 /// This struct also contains a string with additional info
 /// when it is relevant and available.
+///
+/// The decoder uses the same error type, see the conversion from
+/// [`DriverError`] for how the errors of the decoder API are mapped.
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EncodeError {
     kind: ErrorKind,
@@ -119,6 +128,15 @@ pub struct EncodeError {
 }
 
 impl EncodeError {
+    /// Create an error which did not come from the driver, for example
+    /// when the safe wrapper detects invalid usage before calling the API.
+    pub(crate) fn new(kind: ErrorKind, string: impl Into<String>) -> Self {
+        Self {
+            kind,
+            string: Some(string.into()),
+        }
+    }
+
     /// Getter for the error kind.
     #[must_use]
     pub fn kind(&self) -> ErrorKind {
@@ -143,6 +161,19 @@ impl fmt::Display for EncodeError {
 
 impl Error for EncodeError {}
 
+/// Errors from CUDA calls made by the safe wrapper, such as allocating
+/// device memory, are converted to [`ErrorKind::OutOfMemory`] or
+/// [`ErrorKind::Generic`] with the CUDA error as the string.
+impl From<DriverError> for EncodeError {
+    fn from(err: DriverError) -> Self {
+        let kind = match err.0 {
+            CUresult::CUDA_ERROR_OUT_OF_MEMORY => ErrorKind::OutOfMemory,
+            _ => ErrorKind::Generic,
+        };
+        Self::new(kind, err.to_string())
+    }
+}
+
 impl From<NVENCSTATUS> for ErrorKind {
     fn from(status: NVENCSTATUS) -> Self {
         match status {
@@ -211,7 +242,13 @@ impl NVENCSTATUS {
     /// // Unfortunately, it's not always helpful.
     /// assert_eq!(error.string(), Some("EncodeAPI Internal Error."));
     /// ```
-    pub fn result(self, encoder: &Encoder) -> Result<(), EncodeError> {
+    pub fn result<D: EncodeDevice>(self, encoder: &Encoder<D>) -> Result<(), EncodeError> {
+        self.result_raw(&encoder.raw)
+    }
+
+    /// Same as [`NVENCSTATUS::result`], for types which only keep the
+    /// encoder pointer.
+    pub(crate) fn result_raw(self, encoder: &RawEncoder) -> Result<(), EncodeError> {
         self.result_without_string().map_err(|mut err| {
             err.string = match err.kind {
                 // Avoid getting the string if it is not needed.
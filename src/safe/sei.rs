@@ -0,0 +1,189 @@
+//! Defines [`SeiMessage`], the typed SEI messages and AV1 metadata which the
+//! [`VideoParser`](super::VideoParser) extracts from a stream.
+
+use std::mem;
+
+use crate::sys::{
+    cuviddec::cudaVideoCodec,
+    nvcuvid::{HEVCSEITIMECODE, HEVCTIMECODESET},
+};
+
+/// The payload type of user data unregistered SEI messages.
+const USER_DATA_UNREGISTERED: u8 = 5;
+/// The payload type of time code SEI messages in HEVC.
+const TIME_CODE: u8 = 136;
+/// The payload type of mastering display colour volume SEI messages.
+const MASTERING_DISPLAY: u8 = 137;
+/// The payload type of content light level information SEI messages.
+const CONTENT_LIGHT_LEVEL: u8 = 144;
+/// The metadata type of content light level metadata in AV1.
+const AV1_CONTENT_LIGHT_LEVEL: u8 = 1;
+/// The metadata type of mastering display metadata in AV1.
+const AV1_MASTERING_DISPLAY: u8 = 2;
+
+/// The mastering display colour volume of HDR content.
+///
+/// The values are kept in the units of the codec. For H.264 and HEVC the
+/// primaries are in the order green, blue, red, the chromaticities are in
+/// units of 0.00002 and the luminances in units of 0.0001 cd/m². For AV1 the
+/// primaries are in the order red, green, blue, the chromaticities are 0.16
+/// fixed point numbers, the maximum luminance is a 24.8 and the minimum
+/// luminance an 18.14 fixed point number in cd/m².
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct MasteringDisplay {
+    /// The `(x, y)` chromaticities of the three display primaries.
+    pub primaries: [(u16, u16); 3],
+    /// The `(x, y)` chromaticity of the white point.
+    pub white_point: (u16, u16),
+    /// The maximum luminance of the display.
+    pub max_luminance: u32,
+    /// The minimum luminance of the display.
+    pub min_luminance: u32,
+}
+
+/// The content light level of HDR content, in cd/m².
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ContentLightLevel {
+    /// The maximum light level of any pixel (`MaxCLL`).
+    pub max_content_light_level: u16,
+    /// The maximum average light level of any frame (`MaxFALL`).
+    pub max_frame_average_light_level: u16,
+}
+
+/// A time code of a picture, from an HEVC time code SEI message.
+///
+/// Fields which the message leaves out because they did not change since
+/// the previous time code are `None`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct TimeCode {
+    /// The hours of the time code.
+    pub hours: Option<u8>,
+    /// The minutes of the time code.
+    pub minutes: Option<u8>,
+    /// The seconds of the time code.
+    pub seconds: Option<u8>,
+    /// The frame number within the second.
+    pub frames: u16,
+    /// Whether frame numbers were dropped, as in drop frame time codes.
+    pub dropped_frames: bool,
+    /// Whether the time code is discontinuous with the previous one.
+    pub discontinuity: bool,
+}
+
+impl From<&HEVCTIMECODESET> for TimeCode {
+    fn from(set: &HEVCTIMECODESET) -> Self {
+        let full = set.full_timestamp_flag != 0;
+        let seconds = full || set.seconds_flag != 0;
+        let minutes = seconds && (full || set.minutes_flag != 0);
+        let hours = minutes && (full || set.hours_flag != 0);
+        Self {
+            hours: hours.then_some(set.hours_value),
+            minutes: minutes.then_some(set.minutes_value),
+            seconds: seconds.then_some(set.seconds_value),
+            frames: set.n_frames,
+            dropped_frames: set.cnt_dropped_flag != 0,
+            discontinuity: set.discontinuity_flag != 0,
+        }
+    }
+}
+
+/// An SEI message of an H.264 or HEVC stream, or a metadata OBU of an AV1
+/// stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeiMessage {
+    /// User data identified by a UUID.
+    UserDataUnregistered {
+        /// The UUID identifying the kind of data.
+        uuid: [u8; 16],
+        /// The data following the UUID.
+        data: Vec<u8>,
+    },
+    /// The mastering display colour volume of HDR content.
+    MasteringDisplay(MasteringDisplay),
+    /// The content light level of HDR content.
+    ContentLightLevel(ContentLightLevel),
+    /// The time codes of the picture, one per field or frame.
+    TimeCodes(Vec<TimeCode>),
+    /// Any other message, or a message which could not be parsed.
+    Other {
+        /// The SEI payload type, or the metadata type for AV1.
+        payload_type: u8,
+        /// The payload of the message.
+        data: Vec<u8>,
+    },
+}
+
+impl SeiMessage {
+    /// Parse the payload of a message of the given type.
+    pub(crate) fn parse(codec: cudaVideoCodec, payload_type: u8, data: &[u8]) -> Self {
+        let parsed = match codec {
+            cudaVideoCodec::cudaVideoCodec_H264 | cudaVideoCodec::cudaVideoCodec_HEVC => {
+                match payload_type {
+                    USER_DATA_UNREGISTERED => parse_user_data(data),
+                    TIME_CODE if codec == cudaVideoCodec::cudaVideoCodec_HEVC => {
+                        parse_time_codes(data)
+                    }
+                    MASTERING_DISPLAY => parse_mastering_display(data),
+                    CONTENT_LIGHT_LEVEL => parse_content_light_level(data),
+                    _ => None,
+                }
+            }
+            cudaVideoCodec::cudaVideoCodec_AV1 => match payload_type {
+                AV1_MASTERING_DISPLAY => parse_mastering_display(data),
+                AV1_CONTENT_LIGHT_LEVEL => parse_content_light_level(data),
+                _ => None,
+            },
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Self::Other {
+            payload_type,
+            data: data.to_vec(),
+        })
+    }
+}
+
+fn parse_user_data(data: &[u8]) -> Option<SeiMessage> {
+    let uuid = data.get(..16)?.try_into().ok()?;
+    Some(SeiMessage::UserDataUnregistered {
+        uuid,
+        data: data[16..].to_vec(),
+    })
+}
+
+/// The parser already decodes HEVC time codes into a [`HEVCSEITIMECODE`].
+fn parse_time_codes(data: &[u8]) -> Option<SeiMessage> {
+    if data.len() < mem::size_of::<HEVCSEITIMECODE>() {
+        return None;
+    }
+    // SAFETY: The buffer is large enough, and any bytes are a valid
+    // `HEVCSEITIMECODE`.
+    let time_code = unsafe { data.as_ptr().cast::<HEVCSEITIMECODE>().read_unaligned() };
+    let count = usize::from(time_code.num_clock_ts).min(time_code.time_code_set.len());
+    Some(SeiMessage::TimeCodes(
+        time_code.time_code_set[..count]
+            .iter()
+            .filter(|set| set.clock_timestamp_flag != 0)
+            .map(TimeCode::from)
+            .collect(),
+    ))
+}
+
+fn parse_mastering_display(data: &[u8]) -> Option<SeiMessage> {
+    let u16_at = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?));
+    let u32_at = |i: usize| Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?));
+    let point_at = |i: usize| Some((u16_at(i)?, u16_at(i + 2)?));
+    Some(SeiMessage::MasteringDisplay(MasteringDisplay {
+        primaries: [point_at(0)?, point_at(4)?, point_at(8)?],
+        white_point: point_at(12)?,
+        max_luminance: u32_at(16)?,
+        min_luminance: u32_at(20)?,
+    }))
+}
+
+fn parse_content_light_level(data: &[u8]) -> Option<SeiMessage> {
+    let u16_at = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?));
+    Some(SeiMessage::ContentLightLevel(ContentLightLevel {
+        max_content_light_level: u16_at(0)?,
+        max_frame_average_light_level: u16_at(2)?,
+    }))
+}
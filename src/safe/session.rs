@@ -5,17 +5,25 @@
 //! frames. The [`Session`] also stores some information such as the encode
 //! width and height so that you do not have to keep repeating it each time.
 
-use std::fmt::Debug;
+use std::{cell::Cell, fmt::Debug, ptr};
 
-use super::{api::ENCODE_API, encoder::Encoder, result::EncodeError};
+use super::{
+    api::ENCODE_API,
+    device::{CudaDevice, EncodeDevice},
+    encoder::Encoder,
+    result::{EncodeError, ErrorKind},
+};
 use crate::{
     sys::nvEncodeAPI::{
         GUID,
         NV_ENC_BUFFER_FORMAT,
+        NV_ENC_CAPS,
         NV_ENC_CODEC_AV1_GUID,
         NV_ENC_CODEC_H264_GUID,
         NV_ENC_CODEC_HEVC_GUID,
         NV_ENC_CODEC_PIC_PARAMS,
+        NV_ENC_LOOKAHEAD_PIC_PARAMS,
+        NV_ENC_LOOKAHEAD_PIC_PARAMS_VER,
         NV_ENC_PIC_PARAMS,
         NV_ENC_PIC_PARAMS_AV1,
         NV_ENC_PIC_PARAMS_H264,
@@ -23,9 +31,11 @@ use crate::{
         NV_ENC_PIC_PARAMS_VER,
         NV_ENC_PIC_STRUCT,
         NV_ENC_PIC_TYPE,
+        NV_ENC_RC_PARAMS,
     },
     EncoderInput,
     EncoderOutput,
+    ReconBuffer,
 };
 
 /// An encoding session to create input/output buffers and encode frames.
@@ -34,15 +44,23 @@ use crate::{
 /// encode frames using the session. On drop, the session will automatically
 /// send an empty EOS frame to flush the encoder.
 #[derive(Debug)]
-pub struct Session {
-    pub(crate) encoder: Encoder,
+pub struct Session<D: EncodeDevice = CudaDevice> {
+    pub(crate) encoder: Encoder<D>,
     pub(crate) width: u32,
     pub(crate) height: u32,
     pub(crate) buffer_format: NV_ENC_BUFFER_FORMAT,
     pub(crate) encode_guid: GUID,
+    pub(crate) rc_params: NV_ENC_RC_PARAMS,
+    pub(crate) num_state_buffers: u32,
+    pub(crate) recon_frame_output: bool,
+    pub(crate) alpha_layer_encoding: bool,
+    pub(crate) intra_refresh: bool,
+    pub(crate) pending_intra_refresh: Cell<u32>,
+    // The number of B frames between reference frames.
+    pub(crate) reorder_delay: u32,
 }
 
-impl Session {
+impl<D: EncodeDevice> Session<D> {
     /// Get the encoder used for this session.
     ///
     /// This might be useful if you want to use some of
@@ -80,7 +98,7 @@ impl Session {
     ///     .get_supported_input_formats(encode_guid);
     /// ```
     #[must_use]
-    pub fn get_encoder(&self) -> &Encoder {
+    pub fn get_encoder(&self) -> &Encoder<D> {
         &self.encoder
     }
 
@@ -93,6 +111,10 @@ impl Session {
     /// Could error if the encode picture parameters were invalid or otherwise
     /// incorrect, or if we run out memory.
     ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if an alpha
+    /// input is provided but alpha layer encoding is not enabled.
+    ///
     /// There are two recoverable errors:
     /// - If this returns an error with
     ///   [`ErrorKind::EncoderBusy`](super::ErrorKind::EncoderBusy) then you
@@ -173,8 +195,48 @@ impl Session {
         &self,
         input_buffer: &mut I,
         output_bitstream: &mut O,
-        params: EncodePictureParams,
+        params: EncodePictureParams<'_>,
+    ) -> Result<(), EncodeError> {
+        let mut encode_pic_params = self.pic_params(input_buffer, output_bitstream, params)?;
+        self.submit_picture(&mut encode_pic_params)
+    }
+
+    /// Submit the picture params built by [`Session::pic_params`] to the
+    /// encoder.
+    ///
+    /// A pending intra refresh request is cleared once the encoder has
+    /// accepted the frame.
+    pub(crate) fn submit_picture(
+        &self,
+        encode_pic_params: &mut NV_ENC_PIC_PARAMS,
     ) -> Result<(), EncodeError> {
+        let result =
+            unsafe { (ENCODE_API.encode_picture)(self.encoder.raw.ptr, encode_pic_params) }
+                .result(&self.encoder);
+        match &result {
+            Err(err) if err.kind() != ErrorKind::NeedMoreInput => {}
+            _ => self.pending_intra_refresh.set(0),
+        }
+        result
+    }
+
+    /// Build the [`NV_ENC_PIC_PARAMS`] used by [`Session::encode_picture`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an alpha input is provided but alpha layer encoding
+    /// is not enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if codec specific parameters are provided for a different codec
+    /// than the one used in the session.
+    pub(crate) fn pic_params<I: EncoderInput, O: EncoderOutput>(
+        &self,
+        input_buffer: &mut I,
+        output_bitstream: &mut O,
+        mut params: EncodePictureParams<'_>,
+    ) -> Result<NV_ENC_PIC_PARAMS, EncodeError> {
         if let Some(codec_params) = &params.codec_params {
             assert_eq!(
                 codec_params.get_codec_guid(),
@@ -182,7 +244,30 @@ impl Session {
                 "The provided codec specific params must match the codec used"
             );
         }
-        let mut encode_pic_params = NV_ENC_PIC_PARAMS {
+        if params.alpha_input.is_some() && !self.alpha_layer_encoding {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                "An alpha input can only be provided if alpha layer encoding is enabled.",
+            ));
+        }
+        let intra_refresh_frames = self.pending_intra_refresh.get();
+        if intra_refresh_frames > 0 {
+            let codec_params = params
+                .codec_params
+                .get_or_insert_with(|| CodecPictureParams::default_for(self.encode_guid));
+            match codec_params {
+                CodecPictureParams::H264(params) => {
+                    params.forceIntraRefreshWithFrameCnt = intra_refresh_frames;
+                }
+                CodecPictureParams::Hevc(params) => {
+                    params.forceIntraRefreshWithFrameCnt = intra_refresh_frames;
+                }
+                CodecPictureParams::Av1(params) => {
+                    params.forceIntraRefreshWithFrameCnt = intra_refresh_frames;
+                }
+            }
+        }
+        Ok(NV_ENC_PIC_PARAMS {
             version: NV_ENC_PIC_PARAMS_VER,
             inputWidth: self.width,
             inputHeight: self.height,
@@ -194,12 +279,113 @@ impl Session {
             inputTimeStamp: params.input_timestamp,
             codecPicParams: params.codec_params.map(Into::into).unwrap_or_default(),
             pictureType: params.picture_type,
+            outputReconBuffer: params
+                .recon_buffer
+                .map_or(ptr::null_mut(), ReconBuffer::handle),
+            alphaBuffer: params
+                .alpha_input
+                .map_or(ptr::null_mut(), EncoderInput::handle),
+            ..Default::default()
+        })
+    }
+
+    /// Submit a frame to the lookahead stage of the encoder.
+    ///
+    /// This is used for external lookahead, where the lookahead is run
+    /// separately from encoding. The client should submit
+    /// `lookaheadDepth` plus one frames using this function before calling
+    /// [`Session::encode_picture`] for the first frame, and then keep the
+    /// lookahead queue ahead of the encoded frames. The same input buffers
+    /// must later be passed to [`Session::encode_picture`] in the same order,
+    /// so they must stay available until their encode has completed.
+    ///
+    /// External lookahead has to be enabled when starting the session by
+    /// setting both `enableLookahead` and `enableExtLookahead` in the
+    /// [`NV_ENC_RC_PARAMS`] of the encode config passed to
+    /// [`EncoderInitParams::encode_config`](super::EncoderInitParams::encode_config).
+    ///
+    /// `picture_type` only needs to be set if picture type decision was not
+    /// enabled for the session, otherwise pass
+    /// [`NV_ENC_PIC_TYPE::NV_ENC_PIC_TYPE_UNKNOWN`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if external
+    /// lookahead was not enabled for the session, and an error with
+    /// [`ErrorKind::UnsupportedParam`](super::ErrorKind::UnsupportedParam) if
+    /// the encoder does not support lookahead for the session codec.
+    ///
+    /// Could also error if the input buffer is invalid or if we run out of
+    /// memory.
+    pub fn lookahead_picture<I: EncoderInput>(
+        &self,
+        input_buffer: &mut I,
+        picture_type: NV_ENC_PIC_TYPE,
+    ) -> Result<(), EncodeError> {
+        if self.rc_params.enableLookahead() == 0 || self.rc_params.enableExtLookahead() == 0 {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "External lookahead was not enabled in the rate control params of the session.",
+            ));
+        }
+        let lookahead_supported = self
+            .encoder
+            .get_encode_caps(self.encode_guid, NV_ENC_CAPS::NV_ENC_CAPS_SUPPORT_LOOKAHEAD)?;
+        if lookahead_supported == 0 {
+            return Err(EncodeError::new(
+                ErrorKind::UnsupportedParam,
+                "The encoder does not support lookahead for this codec.",
+            ));
+        }
+
+        let mut lookahead_pic_params = NV_ENC_LOOKAHEAD_PIC_PARAMS {
+            version: NV_ENC_LOOKAHEAD_PIC_PARAMS_VER,
+            inputBuffer: input_buffer.handle(),
+            pictureType: picture_type,
             ..Default::default()
         };
-        unsafe { (ENCODE_API.encode_picture)(self.encoder.ptr, &mut encode_pic_params) }
+        unsafe { (ENCODE_API.lookahead_picture)(self.encoder.raw.ptr, &mut lookahead_pic_params) }
             .result(&self.encoder)
     }
 
+    /// Request an intra refresh spread over the next `frames` frames.
+    ///
+    /// The request is applied to the next frame submitted with
+    /// [`Session::encode_picture`], by setting `forceIntraRefreshWithFrameCnt`
+    /// in its codec specific parameters. If a request is already pending, it
+    /// is replaced.
+    ///
+    /// Intra refresh has to be enabled in the codec config when starting the
+    /// session, for example using
+    /// [`NV_ENC_CONFIG_H264::intra_refresh`](crate::sys::nvEncodeAPI::NV_ENC_CONFIG_H264::intra_refresh)
+    /// with [`IntraRefresh::on_demand`](super::IntraRefresh::on_demand).
+    /// It cannot be used if the GOP structure uses B frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidCall`](super::ErrorKind::InvalidCall) if intra
+    /// refresh was not enabled for the session, and an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if
+    /// `frames` is 0.
+    pub fn request_intra_refresh(&self, frames: u32) -> Result<(), EncodeError> {
+        if !self.intra_refresh {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "Intra refresh was not enabled in the codec config of the session.",
+            ));
+        }
+        if frames == 0 {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                "An intra refresh must span at least one frame.",
+            ));
+        }
+        self.pending_intra_refresh.set(frames);
+        Ok(())
+    }
+
     /// Send an EOS notifications to flush the encoder.
     ///
     /// This function is called automatically on drop, but if you wish to
@@ -214,13 +400,13 @@ impl Session {
     /// should retry after a few milliseconds.
     pub fn end_of_stream(&self) -> Result<(), EncodeError> {
         let mut encode_pic_params = NV_ENC_PIC_PARAMS::end_of_stream();
-        unsafe { (ENCODE_API.encode_picture)(self.encoder.ptr, &mut encode_pic_params) }
+        unsafe { (ENCODE_API.encode_picture)(self.encoder.raw.ptr, &mut encode_pic_params) }
             .result(&self.encoder)
     }
 }
 
 /// Send an EOS notifications on drop to flush the encoder.
-impl Drop for Session {
+impl<D: EncodeDevice> Drop for Session<D> {
     fn drop(&mut self) {
         if !std::thread::panicking() {
             self.end_of_stream()
@@ -231,7 +417,7 @@ impl Drop for Session {
 
 /// Optional parameters for [`Session::encode_picture`].
 #[allow(missing_debug_implementations)] // CodecPictureParams doesn't implement Debug
-pub struct EncodePictureParams {
+pub struct EncodePictureParams<'a> {
     /// Opaque data used for identifying the corresponding encoded frame
     pub input_timestamp: u64,
     /// The picture type to use, if picture type decision is disabled in the
@@ -239,14 +425,22 @@ pub struct EncodePictureParams {
     pub picture_type: NV_ENC_PIC_TYPE,
     /// Codec-specific parameters
     pub codec_params: Option<CodecPictureParams>,
+    /// Buffer to write the reconstructed frame into, if reconstructed frame
+    /// output is enabled in the encoder
+    pub recon_buffer: Option<&'a ReconBuffer<'a>>,
+    /// Input buffer containing the alpha plane, if alpha layer encoding is
+    /// enabled in the HEVC config
+    pub alpha_input: Option<&'a mut dyn EncoderInput>,
 }
 
-impl Default for EncodePictureParams {
+impl Default for EncodePictureParams<'_> {
     fn default() -> Self {
         Self {
             input_timestamp: 0,
             picture_type: NV_ENC_PIC_TYPE::NV_ENC_PIC_TYPE_UNKNOWN,
             codec_params: None,
+            recon_buffer: None,
+            alpha_input: None,
         }
     }
 }
@@ -273,6 +467,23 @@ impl CodecPictureParams {
             Self::Av1(_) => NV_ENC_CODEC_AV1_GUID,
         }
     }
+
+    /// Create default parameters for the codec represented by `codec_guid`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the GUID does not represent H.264, HEVC or AV1.
+    pub(crate) fn default_for(codec_guid: GUID) -> Self {
+        if codec_guid == NV_ENC_CODEC_H264_GUID {
+            Self::H264(NV_ENC_PIC_PARAMS_H264::default())
+        } else if codec_guid == NV_ENC_CODEC_HEVC_GUID {
+            Self::Hevc(NV_ENC_PIC_PARAMS_HEVC::default())
+        } else if codec_guid == NV_ENC_CODEC_AV1_GUID {
+            Self::Av1(NV_ENC_PIC_PARAMS_AV1::default())
+        } else {
+            panic!("Unknown codec GUID")
+        }
+    }
 }
 
 impl From<CodecPictureParams> for NV_ENC_CODEC_PIC_PARAMS {
@@ -0,0 +1,186 @@
+//! Defines [`SharedSession`], a [`Session`] whose buffers do not borrow it.
+//!
+//! NVIDIA recommends submitting frames on one thread and reading the output
+//! on another. With a [`SharedSession`] the input buffers, output bitstreams
+//! and registered resources are `'static`, so they can be moved into other
+//! threads without scoped threads, while frames are still submitted from the
+//! thread owning the session.
+
+use std::{cell::Cell, ffi::c_void, marker::PhantomData, ops::Deref, sync::Arc};
+
+use cudarc::driver::{DevicePtr, MappedBuffer};
+
+use super::{
+    buffer::{Bitstream, Buffer, RegisteredResource},
+    device::CudaDevice,
+    encoder::{EncoderRef, RawEncoder},
+    result::EncodeError,
+    session::Session,
+};
+use crate::sys::nvEncodeAPI::NV_ENC_INPUT_RESOURCE_TYPE;
+
+impl Session<CudaDevice> {
+    /// Turn the session into a [`SharedSession`], whose resources can be sent
+    /// to other threads.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::{sync::mpsc, thread};
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     Bitstream, Buffer, EncodePictureParams, Encoder, EncoderInitParams, ErrorKind, Frame,
+    /// #     PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// let session = session.into_shared();
+    /// let data = vec![0; PixelFormat::Nv12.frame_size(WIDTH, HEIGHT, WIDTH)];
+    /// let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    ///
+    /// // Read the output on a separate thread.
+    /// let (sender, receiver) = mpsc::channel::<(Buffer, Bitstream)>();
+    /// let reader = thread::spawn(move || {
+    ///     let mut encoded = Vec::new();
+    ///     for (_input, mut output) in receiver {
+    ///         encoded.extend_from_slice(output.lock().unwrap().data());
+    ///     }
+    ///     encoded
+    /// });
+    ///
+    /// let mut in_flight = Vec::new();
+    /// for _ in 0..32 {
+    ///     let mut input = session.create_input_buffer().unwrap();
+    ///     let mut output = session.create_output_bitstream().unwrap();
+    ///     input.lock().unwrap().write_frame(&frame).unwrap();
+    ///     let result =
+    ///         session.encode_picture(&mut input, &mut output, EncodePictureParams::default());
+    ///     in_flight.push((input, output));
+    ///     match result {
+    ///         Ok(()) => in_flight.drain(..).for_each(|frame| sender.send(frame).unwrap()),
+    ///         Err(err) if err.kind() == ErrorKind::NeedMoreInput => {}
+    ///         Err(err) => panic!("{err:?}"),
+    ///     }
+    /// }
+    /// session.end_of_stream().unwrap();
+    /// in_flight.drain(..).for_each(|frame| sender.send(frame).unwrap());
+    /// drop(sender);
+    ///
+    /// let _encoded = reader.join().unwrap();
+    /// ```
+    #[must_use]
+    pub fn into_shared(self) -> SharedSession {
+        SharedSession {
+            owner: Arc::new(SessionOwner(self)),
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+/// A [`Session`] using CUDA as the encode device, whose resources keep it
+/// alive.
+///
+/// This type is created via [`Session::into_shared`]. It dereferences to the
+/// [`Session`], so frames are encoded the same way. The input buffers, output
+/// bitstreams and registered resources created by it keep the encoder alive
+/// instead of borrowing the session, so they are `'static` and can be sent to
+/// other threads. The encoder is flushed and destroyed once the session and
+/// the last resource are dropped.
+///
+/// Like the [`Session`], it can be sent to another thread but not shared
+/// between threads, so frames are always submitted from a single thread.
+#[derive(Debug)]
+pub struct SharedSession {
+    owner: Arc<SessionOwner>,
+    // The owner is `Sync` so resources can keep it alive from other threads,
+    // but the session itself must not be used from several threads at once.
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Deref for SharedSession {
+    type Target = Session<CudaDevice>;
+
+    fn deref(&self) -> &Session<CudaDevice> {
+        &self.owner.0
+    }
+}
+
+/// Keeps the [`Session`] of a [`SharedSession`] alive for its resources.
+///
+/// Only the [`SharedSession`] can reach the session itself, the resources
+/// only get the raw encoder.
+#[derive(Debug)]
+pub(crate) struct SessionOwner(Session<CudaDevice>);
+
+// SAFETY: Through a shared reference other threads can only get the raw
+// encoder, which resources use to lock, map and destroy themselves. NVIDIA
+// supports doing this on a different thread than the one submitting frames.
+unsafe impl Sync for SessionOwner {}
+
+impl SessionOwner {
+    /// Getter for the raw encoder.
+    pub(crate) fn raw(&self) -> &RawEncoder {
+        &self.0.encoder.raw
+    }
+}
+
+impl SharedSession {
+    /// Create a [`Buffer`] which keeps the session alive.
+    ///
+    /// See [`Session::create_input_buffer`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if we run out of memory.
+    pub fn create_input_buffer(&self) -> Result<Buffer<'static>, EncodeError> {
+        self.create_input_buffer_with(EncoderRef::Shared(Arc::clone(&self.owner)))
+    }
+
+    /// Create a [`Bitstream`] which keeps the session alive.
+    ///
+    /// See [`Session::create_output_bitstream`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if we run out of memory.
+    pub fn create_output_bitstream(&self) -> Result<Bitstream<'static>, EncodeError> {
+        self.create_output_bitstream_with(EncoderRef::Shared(Arc::clone(&self.owner)))
+    }
+
+    /// Create a [`RegisteredResource`] from a [`MappedBuffer`], which keeps
+    /// the session alive.
+    ///
+    /// See [`Session::register_cuda_resource`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if registration fails,
+    /// if the resource is invalid, or if we run out of memory.
+    pub fn register_cuda_resource(
+        &self,
+        pitch: u32,
+        mapped_buffer: MappedBuffer,
+    ) -> Result<RegisteredResource<'static, MappedBuffer>, EncodeError> {
+        let stream = self.encoder.cuda_ctx().default_stream();
+        let (device_ptr, _) = mapped_buffer.device_ptr(&stream);
+        self.register_generic_resource_with(
+            EncoderRef::Shared(Arc::clone(&self.owner)),
+            mapped_buffer,
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
+            device_ptr as *mut c_void,
+            pitch,
+        )
+    }
+}
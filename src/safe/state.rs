@@ -0,0 +1,251 @@
+//! Defines [`EncoderStateSlot`] for saving and restoring the encoder state.
+//!
+//! The encoder can save its state into one of several state buffers while
+//! encoding a frame. The state can later be restored, so that the same frame
+//! can be encoded again with different parameters without advancing the
+//! encoder state. This is useful for quality-targeting retry loops.
+
+use std::ptr;
+
+use super::{
+    api::ENCODE_API,
+    device::{CudaDevice, EncodeDevice},
+    result::{EncodeError, ErrorKind},
+    session::{EncodePictureParams, Session},
+};
+use crate::{
+    sys::nvEncodeAPI::{
+        NV_ENC_RESTORE_ENCODER_STATE_PARAMS,
+        NV_ENC_RESTORE_ENCODER_STATE_PARAMS_VER,
+        NV_ENC_STATE_RESTORE_TYPE,
+    },
+    EncoderInput,
+    EncoderOutput,
+};
+
+/// Functions for saving and restoring the encoder state.
+impl<D: EncodeDevice> Session<D> {
+    /// Get the [`EncoderStateSlot`] for the state buffer at `index`.
+    ///
+    /// State buffers have to be allocated when starting the session using
+    /// [`EncoderInitParams::num_state_buffers`](super::EncoderInitParams::num_state_buffers).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if `index`
+    /// is not smaller than the number of state buffers of the session.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::{
+    /// #         NV_ENC_BUFFER_FORMAT::NV_ENC_BUFFER_FORMAT_ARGB,
+    /// #         NV_ENC_CODEC_H264_GUID,
+    /// #         NV_ENC_STATE_RESTORE_TYPE,
+    /// #     },
+    /// #     Bitstream, Encoder, EncoderInitParams, EncodePictureParams,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// # const DATA_LEN: usize = (WIDTH * HEIGHT * 4) as usize;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// // Begin encoder session with a single state buffer.
+    /// let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// initialize_params
+    ///     .framerate(30, 1)
+    ///     .enable_picture_type_decision()
+    ///     .num_state_buffers(1);
+    /// let session = encoder
+    ///     .start_session(NV_ENC_BUFFER_FORMAT_ARGB, initialize_params)
+    ///     .unwrap();
+    ///
+    /// //* Create input and output buffers. *//
+    /// # let mut input_buffer = session.create_input_buffer().unwrap();
+    /// # let mut output_bitstream = session.create_output_bitstream().unwrap();
+    /// # unsafe { input_buffer.lock().unwrap().write(&[0; DATA_LEN]) };
+    ///
+    /// // Encode the frame and save the state.
+    /// let slot = session.state_slot(0).unwrap();
+    /// slot.encode_picture(
+    ///     &mut input_buffer,
+    ///     &mut output_bitstream,
+    ///     EncodePictureParams::default(),
+    /// )
+    /// .unwrap();
+    /// let _first_size = output_bitstream.lock().unwrap().data().len();
+    ///
+    /// // Not happy with the result? Encode the same frame again.
+    /// slot.reencode_picture(
+    ///     NV_ENC_STATE_RESTORE_TYPE::NV_ENC_STATE_RESTORE_FULL,
+    ///     &mut input_buffer,
+    ///     &mut output_bitstream,
+    ///     &mut Vec::<Bitstream>::new(),
+    ///     EncodePictureParams::default(),
+    /// )
+    /// .unwrap();
+    /// let _second_size = output_bitstream.lock().unwrap().data().len();
+    /// ```
+    pub fn state_slot(&self, index: u32) -> Result<EncoderStateSlot<'_, D>, EncodeError> {
+        if index >= self.num_state_buffers {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "State buffer index {index} is out of range, the session only has {} state \
+                     buffers.",
+                    self.num_state_buffers
+                ),
+            ));
+        }
+        Ok(EncoderStateSlot {
+            session: self,
+            index,
+        })
+    }
+}
+
+/// A state buffer of the encoder which can be used to save and restore
+/// the encoder state.
+///
+/// This type is created via [`Session::state_slot`].
+#[derive(Debug)]
+pub struct EncoderStateSlot<'a, D: EncodeDevice = CudaDevice> {
+    session: &'a Session<D>,
+    index: u32,
+}
+
+// Derived impls would require the device to be `Copy`.
+impl<D: EncodeDevice> Clone for EncoderStateSlot<'_, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D: EncodeDevice> Copy for EncoderStateSlot<'_, D> {}
+
+impl<D: EncodeDevice> EncoderStateSlot<'_, D> {
+    /// Getter for the index of the state buffer.
+    #[must_use]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Encode a frame and save the encoder state for this frame into the
+    /// state buffer.
+    ///
+    /// This behaves the same as [`Session::encode_picture`], see it for more
+    /// info.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Session::encode_picture`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if codec specific parameters are provided for a different codec
+    /// than the one used in the session.
+    pub fn encode_picture<I: EncoderInput, O: EncoderOutput>(
+        &self,
+        input_buffer: &mut I,
+        output_bitstream: &mut O,
+        params: EncodePictureParams<'_>,
+    ) -> Result<(), EncodeError> {
+        let mut encode_pic_params =
+            self.session
+                .pic_params(input_buffer, output_bitstream, params)?;
+        encode_pic_params.stateBufferIdx = self.index;
+        self.session.submit_picture(&mut encode_pic_params)
+    }
+
+    /// Restore the encoder state saved in the state buffer.
+    ///
+    /// The `state` specifies which part of the encoder state is restored.
+    /// All previous encodes must have finished before calling this, so the
+    /// output bitstreams should be locked first.
+    ///
+    /// For AV1 with B frames, the encoder might need extra output bitstreams
+    /// to hold overlay frames. In that case the bitstreams from
+    /// `extra_outputs` are used in order, and the number of bitstreams used is
+    /// returned. The client should lock those bitstreams to retrieve the
+    /// overlay frames. For other codecs `extra_outputs` can be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::NeedMoreOutput`](super::ErrorKind::NeedMoreOutput) if the
+    /// encoder needed more extra output bitstreams than were provided.
+    ///
+    /// Could also error if the state buffer does not hold a valid state
+    /// or if we run out of memory.
+    pub fn restore<O: EncoderOutput>(
+        &self,
+        state: NV_ENC_STATE_RESTORE_TYPE,
+        extra_outputs: &mut [O],
+    ) -> Result<usize, EncodeError> {
+        let mut restore_params = NV_ENC_RESTORE_ENCODER_STATE_PARAMS {
+            version: NV_ENC_RESTORE_ENCODER_STATE_PARAMS_VER,
+            bufferIdx: self.index,
+            state,
+            outputBitstream: ptr::null_mut(),
+            ..Default::default()
+        };
+        let mut extra_outputs = extra_outputs.iter_mut();
+        let mut used = 0;
+        loop {
+            match unsafe {
+                (ENCODE_API.restore_encoder_state)(
+                    self.session.encoder.raw.ptr,
+                    &mut restore_params,
+                )
+            }
+            .result(&self.session.encoder)
+            {
+                Ok(()) => return Ok(used),
+                // Retry with another output bitstream, if we have one left.
+                Err(err) if err.kind() == ErrorKind::NeedMoreOutput => {
+                    let extra_output = extra_outputs.next().ok_or(err)?;
+                    restore_params.outputBitstream = extra_output.handle();
+                    used += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Restore the encoder state and encode a frame again.
+    ///
+    /// This is a combination of [`EncoderStateSlot::restore`] and
+    /// [`EncoderStateSlot::encode_picture`]. The frame is encoded starting
+    /// from the restored state, and the new state is saved into the same
+    /// state buffer, so this can be repeated until the result is acceptable.
+    ///
+    /// Returns the number of `extra_outputs` used by the restore. See
+    /// [`EncoderStateSlot::restore`] for more info.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`EncoderStateSlot::restore`] and
+    /// [`EncoderStateSlot::encode_picture`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if codec specific parameters are provided for a different codec
+    /// than the one used in the session.
+    pub fn reencode_picture<I: EncoderInput, O: EncoderOutput, E: EncoderOutput>(
+        &self,
+        state: NV_ENC_STATE_RESTORE_TYPE,
+        input_buffer: &mut I,
+        output_bitstream: &mut O,
+        extra_outputs: &mut [E],
+        params: EncodePictureParams<'_>,
+    ) -> Result<usize, EncodeError> {
+        let used = self.restore(state, extra_outputs)?;
+        self.encode_picture(input_buffer, output_bitstream, params)?;
+        Ok(used)
+    }
+}
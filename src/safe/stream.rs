@@ -0,0 +1,364 @@
+//! Defines [`EncodeStream`] for encoding frames from async code.
+//!
+//! The NVENC calls are blocking, so the [`EncodeStream`] drives an
+//! [`EncodePipeline`](super::EncodePipeline) on a worker thread. Frames are
+//! sent to it through its [`Sink`] implementation and the encoded packets
+//! are received through its [`Stream`] implementation.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use super::{
+    device::CudaDevice,
+    format::PixelFormat,
+    frame::Frame,
+    pipeline::EncodedPacket,
+    result::{EncodeError, ErrorKind},
+    session::{EncodePictureParams, Session},
+};
+
+/// A frame copied out of the caller's memory, so it can be sent to the
+/// worker thread.
+#[derive(Debug)]
+struct OwnedFrame {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    format: PixelFormat,
+    timestamp: u64,
+}
+
+/// The state shared between the [`EncodeStream`] and its worker thread.
+#[derive(Debug, Default)]
+struct Shared {
+    // Frames which have not been submitted yet.
+    frames: VecDeque<OwnedFrame>,
+    // Packets and errors which have not been received yet.
+    packets: VecDeque<Result<EncodedPacket, EncodeError>>,
+    // The number of frames in flight in the pipeline of the worker.
+    in_flight: usize,
+    // Set when the stream is closed, so the worker flushes the encoder.
+    closing: bool,
+    // Set when the stream is dropped, so the worker stops.
+    dropped: bool,
+    // Set when the worker has stopped.
+    done: bool,
+    send_waker: Option<Waker>,
+    recv_waker: Option<Waker>,
+}
+
+impl Shared {
+    fn wake_sender(&mut self) {
+        if let Some(waker) = self.send_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_receiver(&mut self) {
+        if let Some(waker) = self.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Channel {
+    shared: Mutex<Shared>,
+    // Notifies the worker when frames are queued or the stream is closed.
+    worker: Condvar,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // The lock is never held while encoding, so it cannot be poisoned by
+        // a panic in the encoder.
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An async interface for encoding frames into packets.
+///
+/// Frames are sent with the [`Sink`] implementation, either alone, in which
+/// case the frame index is used as the timestamp, or together with a
+/// timestamp as a `(Frame, u64)` tuple. The frame is copied, so it does not
+/// need to outlive the send. The encoded packets, or any errors which
+/// occurred while encoding, are received with the [`Stream`]
+/// implementation. Closing the sink flushes the encoder, after which the
+/// stream ends once all packets have been received.
+///
+/// At most `capacity` frames are queued or in flight in the encoder, after
+/// that sending waits until the encoder produces output.
+///
+/// This type is only available with the `async` feature.
+#[derive(Debug)]
+pub struct EncodeStream {
+    channel: Arc<Channel>,
+    capacity: usize,
+    next_timestamp: u64,
+}
+
+impl EncodeStream {
+    /// Create an [`EncodeStream`] which encodes frames with the `session` on
+    /// a worker thread.
+    ///
+    /// See [`Session::create_encode_pipeline`] for the requirements of
+    /// `capacity`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if
+    /// `capacity` is too small, since sending would wait forever for the
+    /// encoder to produce output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use cudarc::driver::CudaContext;
+    /// # use futures::{SinkExt, StreamExt};
+    /// # use nvidia_video_codec_sdk::{
+    /// #     sys::nvEncodeAPI::NV_ENC_CODEC_H264_GUID,
+    /// #     EncodeStream, Encoder, EncoderInitParams, Frame, PixelFormat,
+    /// # };
+    /// # const WIDTH: u32 = 1920;
+    /// # const HEIGHT: u32 = 1080;
+    /// //* Create encoder. *//
+    /// # let cuda_ctx = CudaContext::new(0).unwrap();
+    /// # let encoder = Encoder::initialize_with_cuda(cuda_ctx).unwrap();
+    ///
+    /// //* Begin encoder session with the NV12 format. *//
+    /// # let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT);
+    /// # initialize_params.framerate(30, 1).enable_picture_type_decision();
+    /// # let session = encoder
+    /// #     .start_session(PixelFormat::Nv12, initialize_params)
+    /// #     .unwrap();
+    ///
+    /// let data = vec![0; PixelFormat::Nv12.frame_size(WIDTH, HEIGHT, WIDTH)];
+    /// let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data).unwrap();
+    ///
+    /// let (mut frames, packets) = EncodeStream::new(session, 8).unwrap().split();
+    /// # futures::executor::block_on(async {
+    /// let send = async {
+    ///     for _ in 0..32 {
+    ///         frames.send(frame).await.unwrap();
+    ///     }
+    ///     frames.close().await.unwrap();
+    /// };
+    /// let receive = packets.map(Result::unwrap).collect::<Vec<_>>();
+    /// let ((), packets) = futures::join!(send, receive);
+    /// assert_eq!(packets.len(), 32);
+    /// # });
+    /// ```
+    pub fn new(session: Session<CudaDevice>, capacity: usize) -> Result<Self, EncodeError> {
+        // Check the capacity before the worker creates the pipeline.
+        session.check_pipeline_capacity(capacity)?;
+        let channel = Arc::new(Channel::default());
+        let worker_channel = Arc::clone(&channel);
+        thread::spawn(move || {
+            encode_worker(&session, capacity, &worker_channel);
+            let mut shared = worker_channel.lock();
+            shared.done = true;
+            shared.wake_sender();
+            shared.wake_receiver();
+        });
+        Ok(Self {
+            channel,
+            capacity,
+            next_timestamp: 0,
+        })
+    }
+
+    /// Queue a frame for the worker thread.
+    fn queue(&mut self, frame: &Frame<'_>, timestamp: u64) -> Result<(), EncodeError> {
+        let mut shared = self.channel.lock();
+        if shared.closing || shared.done {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidCall,
+                "Cannot send frames to a closed encode stream.",
+            ));
+        }
+        shared.frames.push_back(OwnedFrame {
+            data: frame.data().to_vec(),
+            width: frame.width(),
+            height: frame.height(),
+            pitch: frame.pitch(),
+            format: frame.format(),
+            timestamp,
+        });
+        self.next_timestamp = timestamp + 1;
+        self.channel.worker.notify_one();
+        Ok(())
+    }
+
+    fn poll_ready_inner(&self, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        let mut shared = self.channel.lock();
+        if shared.done || shared.frames.len() + shared.in_flight < self.capacity {
+            return Poll::Ready(Ok(()));
+        }
+        shared.send_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_flush_inner(&self, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        let mut shared = self.channel.lock();
+        if shared.done || shared.frames.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        shared.send_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_close_inner(&self, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+        let mut shared = self.channel.lock();
+        if !shared.closing {
+            shared.closing = true;
+            self.channel.worker.notify_one();
+        }
+        if shared.done {
+            return Poll::Ready(Ok(()));
+        }
+        shared.send_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<'a> Sink<Frame<'a>> for EncodeStream {
+    type Error = EncodeError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_ready_inner(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Frame<'a>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let timestamp = this.next_timestamp;
+        this.queue(&frame, timestamp)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush_inner(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_close_inner(cx)
+    }
+}
+
+impl<'a> Sink<(Frame<'a>, u64)> for EncodeStream {
+    type Error = EncodeError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_ready_inner(cx)
+    }
+
+    fn start_send(
+        self: Pin<&mut Self>,
+        (frame, timestamp): (Frame<'a>, u64),
+    ) -> Result<(), Self::Error> {
+        self.get_mut().queue(&frame, timestamp)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush_inner(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_close_inner(cx)
+    }
+}
+
+impl Stream for EncodeStream {
+    type Item = Result<EncodedPacket, EncodeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = self.channel.lock();
+        if let Some(packet) = shared.packets.pop_front() {
+            return Poll::Ready(Some(packet));
+        }
+        if shared.done {
+            return Poll::Ready(None);
+        }
+        shared.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Stops the worker thread without flushing the encoder.
+impl Drop for EncodeStream {
+    fn drop(&mut self) {
+        self.channel.lock().dropped = true;
+        self.channel.worker.notify_one();
+    }
+}
+
+/// Encode the queued frames until the stream is closed or dropped.
+fn encode_worker(session: &Session<CudaDevice>, capacity: usize, channel: &Channel) {
+    let mut pipeline = match session.create_encode_pipeline(capacity) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            channel.lock().packets.push_back(Err(err));
+            return;
+        }
+    };
+    loop {
+        let frame = {
+            let mut shared = channel.lock();
+            loop {
+                if shared.dropped {
+                    return;
+                }
+                if let Some(frame) = shared.frames.pop_front() {
+                    // Keep counting the frame while it is being submitted.
+                    shared.in_flight += 1;
+                    break Some(frame);
+                }
+                if shared.closing {
+                    break None;
+                }
+                shared = channel
+                    .worker
+                    .wait(shared)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+
+        let result = match &frame {
+            Some(frame) => Frame::with_pitch(
+                frame.format,
+                frame.width,
+                frame.height,
+                frame.pitch,
+                &frame.data,
+            )
+            .and_then(|input| {
+                let params = EncodePictureParams {
+                    input_timestamp: frame.timestamp,
+                    ..Default::default()
+                };
+                pipeline.encode_frame(&input, params)
+            }),
+            None => pipeline.finish(),
+        };
+
+        let mut shared = channel.lock();
+        match result {
+            Ok(packets) => shared.packets.extend(packets.into_iter().map(Ok)),
+            Err(err) => shared.packets.push_back(Err(err)),
+        }
+        shared.in_flight = pipeline.in_flight();
+        shared.wake_sender();
+        shared.wake_receiver();
+        if frame.is_none() {
+            return;
+        }
+    }
+}
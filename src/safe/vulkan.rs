@@ -0,0 +1,297 @@
+//! Defines functions for encoding memory which was allocated with Vulkan.
+//!
+//! Vulkan memory which was allocated as exportable can be exported as an
+//! opaque file descriptor, imported into CUDA, and then registered with the
+//! encoder. [`Session::import_vulkan_memory`] does all of this in one step.
+//!
+//! Rendering into the memory with Vulkan and encoding it with the encoder
+//! happen on different queues, so they have to be synchronized. This is done
+//! with a pair of Vulkan semaphores which are exported the same way, see
+//! [`VulkanSync`].
+
+use std::{
+    ffi::c_void,
+    fs::File,
+    mem,
+    os::fd::{AsRawFd, IntoRawFd},
+    sync::Arc,
+};
+
+use cudarc::driver::{
+    sys::{
+        self,
+        CUexternalSemaphore,
+        CUexternalSemaphoreHandleType,
+        CUDA_EXTERNAL_SEMAPHORE_HANDLE_DESC,
+        CUDA_EXTERNAL_SEMAPHORE_SIGNAL_PARAMS,
+        CUDA_EXTERNAL_SEMAPHORE_WAIT_PARAMS,
+    },
+    CudaContext,
+    CudaStream,
+    DevicePtr,
+    MappedBuffer,
+};
+
+use super::{
+    buffer::{EncoderInput, MappedResource, RegisteredResource},
+    device::CudaDevice,
+    format::PixelFormat,
+    result::{EncodeError, ErrorKind},
+    session::Session,
+};
+use crate::sys::nvEncodeAPI::NV_ENC_INPUT_RESOURCE_TYPE;
+
+/// A Vulkan binary semaphore which was imported into CUDA.
+///
+/// The semaphore is destroyed when dropped.
+#[derive(Debug)]
+pub struct VulkanSemaphore {
+    semaphore: CUexternalSemaphore,
+    stream: Arc<CudaStream>,
+}
+
+unsafe impl Send for VulkanSemaphore {}
+
+impl VulkanSemaphore {
+    /// Import a Vulkan binary semaphore from an opaque file descriptor.
+    ///
+    /// The semaphore must have been created with
+    /// `VK_EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_FD_BIT` as an export handle
+    /// type, and `fd` obtained with `vkGetSemaphoreFdKHR`. Ownership of the
+    /// file descriptor is transferred to CUDA.
+    ///
+    /// # Errors
+    ///
+    /// Could error if the file descriptor is not a valid semaphore, or if we
+    /// run out of memory.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an opaque file descriptor of a Vulkan semaphore.
+    pub unsafe fn import(ctx: &Arc<CudaContext>, fd: File) -> Result<Self, EncodeError> {
+        ctx.bind_to_thread()?;
+        let mut semaphore_desc: CUDA_EXTERNAL_SEMAPHORE_HANDLE_DESC = mem::zeroed();
+        semaphore_desc.type_ =
+            CUexternalSemaphoreHandleType::CU_EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_FD;
+        semaphore_desc.handle.fd = fd.as_raw_fd();
+        let mut semaphore = std::ptr::null_mut();
+        sys::cuImportExternalSemaphore(&mut semaphore, &semaphore_desc).result()?;
+        // CUDA owns the file descriptor after a successful import.
+        let _ = fd.into_raw_fd();
+        Ok(Self {
+            semaphore,
+            stream: ctx.default_stream(),
+        })
+    }
+
+    /// Wait for Vulkan to signal the semaphore, blocking the calling thread.
+    ///
+    /// # Errors
+    ///
+    /// Could error if waiting on the semaphore fails.
+    pub fn wait(&self) -> Result<(), EncodeError> {
+        let wait_params: CUDA_EXTERNAL_SEMAPHORE_WAIT_PARAMS = unsafe { mem::zeroed() };
+        unsafe {
+            sys::cuWaitExternalSemaphoresAsync(
+                &self.semaphore,
+                &wait_params,
+                1,
+                self.stream.cu_stream(),
+            )
+        }
+        .result()?;
+        self.stream.synchronize()?;
+        Ok(())
+    }
+
+    /// Signal the semaphore, so that Vulkan can continue.
+    ///
+    /// # Errors
+    ///
+    /// Could error if signaling the semaphore fails.
+    pub fn signal(&self) -> Result<(), EncodeError> {
+        let signal_params: CUDA_EXTERNAL_SEMAPHORE_SIGNAL_PARAMS = unsafe { mem::zeroed() };
+        unsafe {
+            sys::cuSignalExternalSemaphoresAsync(
+                &self.semaphore,
+                &signal_params,
+                1,
+                self.stream.cu_stream(),
+            )
+        }
+        .result()?;
+        Ok(())
+    }
+}
+
+impl Drop for VulkanSemaphore {
+    fn drop(&mut self) {
+        let ctx = self.stream.context();
+        ctx.record_err(ctx.bind_to_thread());
+        ctx.record_err(unsafe { sys::cuDestroyExternalSemaphore(self.semaphore) }.result());
+    }
+}
+
+/// The semaphores used to synchronize Vulkan rendering with encoding.
+///
+/// Vulkan signals `rendered` once it has finished writing a frame into the
+/// memory, and waits on `encoded` before writing the next frame.
+#[derive(Debug)]
+pub struct VulkanSync {
+    /// Signaled by Vulkan when the frame has been rendered.
+    pub rendered: VulkanSemaphore,
+    /// Signaled after the frame has been encoded.
+    pub encoded: VulkanSemaphore,
+}
+
+/// Vulkan memory which was imported into CUDA.
+///
+/// This is the marker of the [`RegisteredResource`] returned by
+/// [`Session::import_vulkan_memory`], so the memory stays imported for as
+/// long as it is registered.
+#[derive(Debug)]
+pub struct VulkanMemory {
+    _buffer: MappedBuffer,
+    sync: Option<VulkanSync>,
+}
+
+unsafe impl Send for RegisteredResource<'_, VulkanMemory> {}
+
+/// Functions for importing Vulkan memory.
+impl Session<CudaDevice> {
+    /// Import Vulkan memory and register it as an encoder input.
+    ///
+    /// The memory must have been allocated with
+    /// `VK_EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_FD_BIT` as an export handle
+    /// type, and `fd` obtained with `vkGetMemoryFdKHR`. Ownership of the file
+    /// descriptor is transferred to CUDA. The memory holds a single frame in
+    /// the given `format` with tightly packed rows, so its pitch is
+    /// [`PixelFormat::min_pitch`].
+    ///
+    /// If `sync` is given, [`RegisteredResource::map_rendered`] waits for
+    /// Vulkan to finish rendering before mapping the memory, and signals
+    /// Vulkan once the encoder is done with it.
+    ///
+    /// See [`Session::register_generic_resource`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with
+    /// [`ErrorKind::InvalidParam`](super::ErrorKind::InvalidParam) if
+    /// `format` is not the buffer format of the session, or if `size` is too
+    /// small to hold a frame.
+    ///
+    /// Could also error if importing or registration fails, or if we run out
+    /// of memory.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an opaque file descriptor of Vulkan memory which is
+    /// `size` bytes large.
+    pub unsafe fn import_vulkan_memory(
+        &self,
+        fd: File,
+        size: u64,
+        format: PixelFormat,
+        sync: Option<VulkanSync>,
+    ) -> Result<RegisteredResource<'_, VulkanMemory>, EncodeError> {
+        if self.buffer_format != format.into() {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "The format {format:?} does not match the buffer format {:?} of the session.",
+                    self.buffer_format
+                ),
+            ));
+        }
+        let pitch = format.min_pitch(self.width);
+        let frame_size = format.frame_size(self.width, self.height, pitch);
+        if size < frame_size as u64 {
+            return Err(EncodeError::new(
+                ErrorKind::InvalidParam,
+                format!(
+                    "A {}x{} frame in the {format:?} format is {frame_size} bytes, but the memory \
+                     is only {size} bytes.",
+                    self.width, self.height,
+                ),
+            ));
+        }
+        let ctx = self.encoder.cuda_ctx();
+        let buffer = ctx.import_external_memory(fd, size)?.map_all()?;
+        let (device_ptr, _) = buffer.device_ptr(&ctx.default_stream());
+        self.register_generic_resource(
+            VulkanMemory {
+                _buffer: buffer,
+                sync,
+            },
+            NV_ENC_INPUT_RESOURCE_TYPE::NV_ENC_INPUT_RESOURCE_TYPE_CUDADEVICEPTR,
+            device_ptr as *mut c_void,
+            pitch,
+        )
+    }
+}
+
+impl RegisteredResource<'_, VulkanMemory> {
+    /// Wait for Vulkan to finish rendering and map the resource so that it
+    /// can be used as an encoder input.
+    ///
+    /// When the returned [`VulkanMappedResource`] is dropped the resource is
+    /// unmapped and Vulkan is signaled that the frame has been encoded. You
+    /// should lock the output bitstream of the encode before that.
+    ///
+    /// Without [`VulkanSync`] this is the same as
+    /// [`RegisteredResource::map`].
+    ///
+    /// # Errors
+    ///
+    /// Could error if waiting on the semaphore or mapping fails.
+    pub fn map_rendered(&mut self) -> Result<VulkanMappedResource<'_>, EncodeError> {
+        if let Some(sync) = &self.marker().sync {
+            sync.rendered.wait()?;
+        }
+        let (mapped, memory) = self.map_with_marker()?;
+        Ok(VulkanMappedResource {
+            mapped: Some(mapped),
+            encoded: memory.sync.as_ref().map(|sync| &sync.encoded),
+        })
+    }
+}
+
+/// A mapped [`RegisteredResource`] of [`VulkanMemory`].
+///
+/// This type is created via [`RegisteredResource::map_rendered`].
+/// The resource is unmapped, and Vulkan signaled, when dropped.
+#[derive(Debug)]
+pub struct VulkanMappedResource<'a> {
+    mapped: Option<MappedResource<'a>>,
+    encoded: Option<&'a VulkanSemaphore>,
+}
+
+impl Drop for VulkanMappedResource<'_> {
+    fn drop(&mut self) {
+        // Unmap before signaling, so Vulkan only continues once the encoder
+        // is done with the memory.
+        drop(self.mapped.take());
+        if let Some(encoded) = self.encoded {
+            encoded
+                .signal()
+                .expect("The semaphore and stream should be valid.");
+        }
+    }
+}
+
+impl EncoderInput for VulkanMappedResource<'_> {
+    fn pitch(&self) -> u32 {
+        self.mapped
+            .as_ref()
+            .expect("The resource should be mapped.")
+            .pitch()
+    }
+
+    fn handle(&mut self) -> *mut c_void {
+        self.mapped
+            .as_mut()
+            .expect("The resource should be mapped.")
+            .handle()
+    }
+}
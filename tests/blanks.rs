@@ -2,6 +2,8 @@ use std::{
     collections::VecDeque,
     fs::OpenOptions,
     io::Write,
+    mem,
+    ops::Range,
     path::Path,
     sync::Arc,
     thread,
@@ -10,11 +12,45 @@ use std::{
 
 use cudarc::driver::CudaContext;
 use nvidia_video_codec_sdk::{
-    sys::nvEncodeAPI::{GUID, NV_ENC_BUFFER_FORMAT, NV_ENC_CODEC_H264_GUID},
+    sys::{
+        cuviddec::{cudaVideoCodec, CUVIDPICPARAMS},
+        nvEncodeAPI::{
+            GUID,
+            NV_ENC_BUFFER_FORMAT,
+            NV_ENC_CODEC_AV1_GUID,
+            NV_ENC_CODEC_H264_GUID,
+            NV_ENC_PIC_PARAMS_H264,
+            NV_ENC_PRESET_P4_GUID,
+            NV_ENC_SEI_PAYLOAD,
+            NV_ENC_TUNING_INFO,
+        },
+    },
+    Av1OperatingPoint,
+    Av1OperatingPointChoice,
+    CodecPictureParams,
+    ContentLightLevel,
+    CudaDevice,
+    DecodePipeline,
+    DecodeStatus,
+    DecodedFrame,
+    Decoder,
+    DecoderInitParams,
+    DisplayInfo,
     EncodeError,
+    EncodePictureParams,
+    EncodedPacket,
     Encoder,
     EncoderInitParams,
     ErrorKind,
+    Frame,
+    MasteringDisplay,
+    ParserHandler,
+    ParserInitParams,
+    PixelFormat,
+    SeiMessage,
+    Session,
+    VideoFormat,
+    VideoParser,
 };
 
 fn encode_blanks<P: AsRef<Path>>(
@@ -130,6 +166,496 @@ fn encode_blanks<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Start a session which encodes NV12 frames at 30 fps.
+fn start_session(
+    cuda_ctx: Arc<CudaContext>,
+    codec: GUID,
+    width: u32,
+    height: u32,
+) -> Result<Session<CudaDevice>, EncodeError> {
+    let encoder = Encoder::initialize_with_cuda(cuda_ctx)?;
+    let mut initialize_params = EncoderInitParams::new(codec, width, height);
+    initialize_params
+        .enable_picture_type_decision()
+        .framerate(30, 1);
+    encoder.start_session(PixelFormat::Nv12, initialize_params)
+}
+
+/// The data of a white NV12 frame.
+fn blank_nv12_frame(width: u32, height: u32) -> Vec<u8> {
+    vec![255; PixelFormat::Nv12.frame_size(width, height, width)]
+}
+
+fn encode_blanks_with_pool(cuda_ctx: Arc<CudaContext>) -> Result<usize, EncodeError> {
+    const FRAMES: usize = 128;
+    const BUFFERS: usize = 16;
+    const WIDTH: u32 = 1920;
+    const HEIGHT: u32 = 1080;
+
+    let session = start_session(cuda_ctx, NV_ENC_CODEC_H264_GUID, WIDTH, HEIGHT)?;
+    let data = blank_nv12_frame(WIDTH, HEIGHT);
+    let frame = Frame::new(PixelFormat::Nv12, WIDTH, HEIGHT, &data)?;
+
+    let mut pool = session.create_buffer_pool(BUFFERS)?;
+    let mut encoded_frames = 0;
+    for _ in 0..FRAMES {
+        while pool.input().is_none() {
+            pool.next_output()
+                .expect("A frame should be finished when all buffers are in flight.")?;
+            encoded_frames += 1;
+        }
+        pool.input()
+            .expect("There should be a free buffer.")
+            .lock()?
+            .write_frame(&frame)?;
+        loop {
+            match pool.submit(Default::default()) {
+                Err(e) if e.kind() == ErrorKind::EncoderBusy => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                result => break result?,
+            }
+        }
+    }
+    pool.finish()?;
+    while let Some(lock) = pool.next_output() {
+        lock?;
+        encoded_frames += 1;
+    }
+    Ok(encoded_frames)
+}
+
+/// Encode white frames with an encode pipeline, one per timestamp, inserting
+/// the SEI messages given as `(payload type, payload)` into each frame.
+fn encode_h264_blanks(
+    cuda_ctx: Arc<CudaContext>,
+    width: u32,
+    height: u32,
+    timestamps: Range<u64>,
+    sei_messages: &[(u32, &[u8])],
+) -> Result<Vec<EncodedPacket>, EncodeError> {
+    let session = start_session(cuda_ctx, NV_ENC_CODEC_H264_GUID, width, height)?;
+    let data = blank_nv12_frame(width, height);
+    let frame = Frame::new(PixelFormat::Nv12, width, height, &data)?;
+    let mut sei_payloads = sei_messages
+        .iter()
+        .map(|&(payload_type, payload)| NV_ENC_SEI_PAYLOAD {
+            payloadSize: payload.len() as u32,
+            payloadType: payload_type,
+            // The encoder only reads the payload.
+            payload: payload.as_ptr().cast_mut(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut pipeline = session.create_encode_pipeline(16)?;
+    let mut packets = Vec::new();
+    for i in timestamps {
+        let params = EncodePictureParams {
+            input_timestamp: i,
+            codec_params: (!sei_payloads.is_empty()).then(|| {
+                CodecPictureParams::H264(NV_ENC_PIC_PARAMS_H264 {
+                    seiPayloadArrayCnt: sei_payloads.len() as u32,
+                    seiPayloadArray: sei_payloads.as_mut_ptr(),
+                    ..Default::default()
+                })
+            }),
+            ..Default::default()
+        };
+        packets.extend(pipeline.encode_frame(&frame, params)?);
+    }
+    packets.extend(pipeline.finish()?);
+    assert_eq!(pipeline.in_flight(), 0);
+    Ok(packets)
+}
+
+/// Encode white frames as AV1 with an encode pipeline, one per timestamp.
+fn encode_av1_blanks(
+    cuda_ctx: Arc<CudaContext>,
+    width: u32,
+    height: u32,
+    timestamps: Range<u64>,
+) -> Result<Vec<EncodedPacket>, EncodeError> {
+    let session = start_session(cuda_ctx, NV_ENC_CODEC_AV1_GUID, width, height)?;
+    let data = blank_nv12_frame(width, height);
+    let frame = Frame::new(PixelFormat::Nv12, width, height, &data)?;
+    let mut pipeline = session.create_encode_pipeline(16)?;
+    let mut packets = Vec::new();
+    for i in timestamps {
+        let params = EncodePictureParams {
+            input_timestamp: i,
+            ..Default::default()
+        };
+        packets.extend(pipeline.encode_frame(&frame, params)?);
+    }
+    packets.extend(pipeline.finish()?);
+    Ok(packets)
+}
+
+fn encode_blanks_with_pipeline(cuda_ctx: Arc<CudaContext>) -> Result<Vec<u64>, EncodeError> {
+    let packets = encode_h264_blanks(cuda_ctx, 1920, 1080, 0..128, &[])?;
+    assert!(packets[0].keyframe);
+    assert!(packets.iter().all(|packet| packet.dts <= packet.pts as i64));
+    Ok(packets.into_iter().map(|packet| packet.pts).collect())
+}
+
+/// Create encode pipelines of the given capacities for a session with two B
+/// frames and a lookahead depth of 4, returning the kind of each error.
+fn create_pipelines_with_capacity(
+    cuda_ctx: Arc<CudaContext>,
+    capacities: [usize; 2],
+) -> Result<[Option<ErrorKind>; 2], EncodeError> {
+    let encoder = Encoder::initialize_with_cuda(cuda_ctx)?;
+    let tuning_info = NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_HIGH_QUALITY;
+    let mut preset_config =
+        encoder.get_preset_config(NV_ENC_CODEC_H264_GUID, NV_ENC_PRESET_P4_GUID, tuning_info)?;
+    let config = &mut preset_config.presetCfg;
+    config.frameIntervalP = 3;
+    config.rcParams.set_enableLookahead(1);
+    config.rcParams.lookaheadDepth = 4;
+    let mut initialize_params = EncoderInitParams::new(NV_ENC_CODEC_H264_GUID, 640, 360);
+    initialize_params
+        .preset_guid(NV_ENC_PRESET_P4_GUID)
+        .tuning_info(tuning_info)
+        .encode_config(config)
+        .enable_picture_type_decision()
+        .framerate(30, 1);
+    let session = encoder.start_session(PixelFormat::Nv12, initialize_params)?;
+    Ok(capacities.map(|capacity| {
+        session
+            .create_encode_pipeline(capacity)
+            .err()
+            .map(|err| err.kind())
+    }))
+}
+
+fn decode_packets(
+    mut decoder: DecodePipeline,
+    packets: &[EncodedPacket],
+) -> Result<Vec<DecodedFrame>, EncodeError> {
+    let mut frames = Vec::new();
+    for packet in packets {
+        frames.extend(decoder.decode(&packet.data, packet.pts as i64)?);
+    }
+    frames.extend(decoder.finish()?);
+    for decoded in &frames {
+        assert_eq!(decoded.format, PixelFormat::Nv12);
+        // Older GPUs and most codecs do not report the status.
+        assert!(matches!(
+            decoded.decode_status,
+            None | Some(DecodeStatus::Success)
+        ));
+        // The encoder produces progressive frames, which are never split.
+        assert!(decoded.progressive && !decoded.second_field);
+        decoded.as_frame()?;
+    }
+    Ok(frames)
+}
+
+fn decode_blanks(cuda_ctx: Arc<CudaContext>) -> Result<Vec<i64>, EncodeError> {
+    let packets = encode_h264_blanks(cuda_ctx.clone(), 1920, 1080, 0..64, &[])?;
+    let frames = decode_packets(
+        DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264)?,
+        &packets,
+    )?;
+    assert!(frames
+        .iter()
+        .all(|frame| (frame.width, frame.height) == (1920, 1080)));
+    Ok(frames.into_iter().map(|frame| frame.pts).collect())
+}
+
+/// Decode AV1 with all layers of the given operating point.
+fn decode_av1_blanks(
+    cuda_ctx: Arc<CudaContext>,
+    operating_point: u32,
+) -> Result<Vec<i64>, EncodeError> {
+    let packets = encode_av1_blanks(cuda_ctx.clone(), 640, 360, 0..16)?;
+    let mut decoder = DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_AV1)?;
+    decoder.av1_operating_point(operating_point, true);
+    let frames = decode_packets(decoder, &packets)?;
+    assert!(frames
+        .iter()
+        .all(|frame| (frame.width, frame.height) == (640, 360)));
+    Ok(frames.into_iter().map(|frame| frame.pts).collect())
+}
+
+/// The mastering display of BT.2020 content graded on a 1000 cd/m² display.
+const MASTERING_DISPLAY: MasteringDisplay = MasteringDisplay {
+    primaries: [(8500, 39850), (6550, 2300), (35400, 14600)],
+    white_point: (15635, 16450),
+    max_luminance: 10_000_000,
+    min_luminance: 50,
+};
+
+/// Round trips SEI messages through the encoder and the decoder.
+fn decode_sei_messages(cuda_ctx: Arc<CudaContext>) -> Result<Vec<DecodedFrame>, EncodeError> {
+    let user_data = [b"telemetry uuid 0".as_slice(), b"frame data"].concat();
+    // SEI messages are big-endian.
+    let mut mastering_display = Vec::new();
+    for (x, y) in MASTERING_DISPLAY
+        .primaries
+        .into_iter()
+        .chain([MASTERING_DISPLAY.white_point])
+    {
+        mastering_display.extend(x.to_be_bytes());
+        mastering_display.extend(y.to_be_bytes());
+    }
+    mastering_display.extend(MASTERING_DISPLAY.max_luminance.to_be_bytes());
+    mastering_display.extend(MASTERING_DISPLAY.min_luminance.to_be_bytes());
+    let content_light_level = [1000_u16.to_be_bytes(), 400_u16.to_be_bytes()].concat();
+    let packets = encode_h264_blanks(cuda_ctx.clone(), 640, 360, 0..8, &[
+        (5, &user_data),
+        (137, &mastering_display),
+        (144, &content_light_level),
+    ])?;
+    decode_packets(
+        DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264)?,
+        &packets,
+    )
+}
+
+fn decode_resolution_changes(cuda_ctx: Arc<CudaContext>) -> Result<Vec<(u32, u32)>, EncodeError> {
+    // Grow, which recreates the decoder, then shrink, which reconfigures it.
+    let mut packets = encode_h264_blanks(cuda_ctx.clone(), 1280, 720, 0..16, &[])?;
+    packets.extend(encode_h264_blanks(
+        cuda_ctx.clone(),
+        1920,
+        1080,
+        16..32,
+        &[],
+    )?);
+    packets.extend(encode_h264_blanks(cuda_ctx.clone(), 640, 360, 32..48, &[])?);
+    let frames = decode_packets(
+        DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264)?,
+        &packets,
+    )?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| (frame.width, frame.height))
+        .collect())
+}
+
+/// Writes the bits of an H.264 NAL unit.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    len: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1) as u8;
+            self.len += 1;
+            if self.len == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Write an unsigned Exp-Golomb code.
+    fn ue(&mut self, value: u32) {
+        let count = 32 - (value + 1).leading_zeros();
+        self.bits(0, count - 1);
+        self.bits(value + 1, count);
+    }
+
+    fn align(&mut self) {
+        while self.len != 0 {
+            self.bits(0, 1);
+        }
+    }
+
+    /// Finish the NAL unit with the given header, adding the start code and
+    /// the emulation prevention bytes.
+    fn into_nal(mut self, header: u8) -> Vec<u8> {
+        self.bits(1, 1);
+        self.align();
+        let mut nal = vec![0, 0, 0, 1, header];
+        let mut zeros = 0;
+        for byte in self.bytes {
+            if zeros == 2 && byte <= 3 {
+                nal.push(3);
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            nal.push(byte);
+        }
+        nal
+    }
+}
+
+/// Build an H.264 stream of interlaced frames, each coded as a top and a
+/// bottom field of uncompressed I_PCM macroblocks, since the encoder cannot
+/// produce interlaced streams. Returns the data of each frame, the first one
+/// starting with the parameter sets.
+fn interlaced_h264_frames(width: u32, height: u32, frames: u32) -> Vec<Vec<u8>> {
+    // Main profile, level 3, picture order count type 2 and fields.
+    let mut sps = BitWriter::default();
+    sps.bits(77, 8);
+    sps.bits(0, 8);
+    sps.bits(30, 8);
+    for value in [0, 0, 2, 1] {
+        sps.ue(value);
+    }
+    sps.bits(0, 1);
+    sps.ue(width / 16 - 1);
+    // Map units are pairs of macroblock rows.
+    sps.ue(height / 32 - 1);
+    // No frame_mbs_only, no mb_adaptive_frame_field, direct_8x8_inference,
+    // no cropping and no VUI.
+    sps.bits(0b00100, 5);
+    let mut pps = BitWriter::default();
+    pps.ue(0);
+    pps.ue(0);
+    pps.bits(0, 2);
+    for _ in 0..3 {
+        pps.ue(0);
+    }
+    pps.bits(0, 3);
+    // No QP offsets, no deblocking control, constrained intra prediction or
+    // redundant pictures.
+    for _ in 0..3 {
+        pps.ue(0);
+    }
+    pps.bits(0, 3);
+    let parameter_sets = [sps.into_nal(0x67), pps.into_nal(0x68)].concat();
+
+    let macroblocks = (width / 16) * (height / 32);
+    (0..frames)
+        .map(|frame| {
+            let fields = [false, true].map(|bottom| {
+                // The first field is an IDR picture, the second one a
+                // reference field of the same frame.
+                let idr = !bottom;
+                let mut slice = BitWriter::default();
+                // first_mb_in_slice, I slice, pic_parameter_set_id.
+                slice.ue(0);
+                slice.ue(2);
+                slice.ue(0);
+                // frame_num, field_pic_flag and bottom_field_flag.
+                slice.bits(0, 4);
+                slice.bits(1, 1);
+                slice.bits(bottom.into(), 1);
+                if idr {
+                    slice.ue(frame % 2);
+                    // no_output_of_prior_pics and long_term_reference.
+                    slice.bits(0, 2);
+                } else {
+                    // adaptive_ref_pic_marking_mode.
+                    slice.bits(0, 1);
+                }
+                // slice_qp_delta.
+                slice.ue(0);
+                for _ in 0..macroblocks {
+                    // I_PCM, followed by the aligned samples.
+                    slice.ue(25);
+                    slice.align();
+                    let luma = if bottom { 60 } else { 200 };
+                    slice.bytes.extend([luma; 256]);
+                    slice.bytes.extend([128; 128]);
+                }
+                slice.into_nal(if idr { 0x65 } else { 0x61 })
+            });
+            let parameter_sets = if frame == 0 { &parameter_sets[..] } else { &[] };
+            [parameter_sets, &fields[0], &fields[1]].concat()
+        })
+        .collect()
+}
+
+fn decode_interlaced(cuda_ctx: Arc<CudaContext>) -> Result<Vec<DecodedFrame>, EncodeError> {
+    let mut decoder = DecodePipeline::new(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264)?;
+    let mut frames = Vec::new();
+    for (i, data) in interlaced_h264_frames(64, 64, 8).iter().enumerate() {
+        frames.extend(decoder.decode(data, 10 * i as i64)?);
+    }
+    frames.extend(decoder.finish()?);
+    Ok(frames)
+}
+
+/// Decodes into letterboxed thumbnails with the low-level decoder API.
+struct ThumbnailHandler {
+    cuda_ctx: Arc<CudaContext>,
+    decoder: Option<Decoder>,
+    thumbnails: Vec<Vec<u8>>,
+    // Checked after parsing, as a failed assertion cannot unwind through the
+    // parser.
+    sizes: Vec<(u32, u32)>,
+    operating_points: Vec<Vec<Av1OperatingPoint>>,
+}
+
+impl ParserHandler for ThumbnailHandler {
+    fn sequence(&mut self, format: &VideoFormat) -> Option<u32> {
+        let mut params =
+            DecoderInitParams::new(format.codec, format.coded_width, format.coded_height);
+        params
+            .num_decode_surfaces(format.min_num_decode_surfaces)
+            .crop(format.display_area)
+            .letterbox(320, 320);
+        self.decoder = Some(Decoder::new(self.cuda_ctx.clone(), &params).ok()?);
+        Some(format.min_num_decode_surfaces)
+    }
+
+    fn decode_picture(&mut self, picture: &mut CUVIDPICPARAMS) -> bool {
+        let Some(decoder) = &self.decoder else {
+            return false;
+        };
+        unsafe { decoder.decode_picture(picture) }.is_ok()
+    }
+
+    fn display_picture(&mut self, info: &DisplayInfo) -> bool {
+        let Some(decoder) = &self.decoder else {
+            return false;
+        };
+        let Ok(frame) = decoder.map_frame(info) else {
+            return false;
+        };
+        self.sizes.push((frame.width(), frame.height()));
+        frame
+            .copy_to_host()
+            .map(|data| self.thumbnails.push(data))
+            .is_ok()
+    }
+
+    fn av1_operating_point(
+        &mut self,
+        operating_points: &[Av1OperatingPoint],
+    ) -> Option<Av1OperatingPointChoice> {
+        // Let the parser choose the default operating point.
+        self.operating_points.push(operating_points.to_vec());
+        None
+    }
+}
+
+/// Decode the packets into thumbnails, returning them and the operating
+/// points of each AV1 sequence header.
+fn decode_thumbnails(
+    cuda_ctx: Arc<CudaContext>,
+    codec: cudaVideoCodec,
+    packets: &[EncodedPacket],
+) -> Result<(Vec<Vec<u8>>, Vec<Vec<Av1OperatingPoint>>), EncodeError> {
+    let handler = ThumbnailHandler {
+        cuda_ctx,
+        decoder: None,
+        thumbnails: Vec::new(),
+        sizes: Vec::new(),
+        operating_points: Vec::new(),
+    };
+    let mut parser = VideoParser::new(&ParserInitParams::new(codec), handler)?;
+    for packet in packets {
+        parser.parse(&packet.data, packet.pts as i64)?;
+    }
+    parser.flush()?;
+    let handler = parser.handler_mut();
+    assert!(handler.sizes.iter().all(|&size| size == (320, 320)));
+    Ok((
+        mem::take(&mut handler.thumbnails),
+        mem::take(&mut handler.operating_points),
+    ))
+}
+
 #[test]
 fn encoder_works() {
     encode_blanks::<&str>(
@@ -149,3 +675,130 @@ fn encode_in_parallel() {
         }
     });
 }
+
+#[test]
+fn buffer_pool_returns_all_frames() {
+    let encoded_frames =
+        encode_blanks_with_pool(CudaContext::new(0).expect("CUDA should be installed.")).unwrap();
+    assert_eq!(encoded_frames, 128);
+}
+
+#[test]
+fn encode_pipeline_returns_all_packets() {
+    let mut timestamps =
+        encode_blanks_with_pipeline(CudaContext::new(0).expect("CUDA should be installed."))
+            .unwrap();
+    timestamps.sort_unstable();
+    assert_eq!(timestamps, (0..128).collect::<Vec<_>>());
+}
+
+#[test]
+fn encode_pipeline_needs_room_for_held_frames() {
+    let kinds =
+        create_pipelines_with_capacity(CudaContext::new(0).expect("CUDA should be installed."), [
+            6, 7,
+        ])
+        .unwrap();
+    assert_eq!(kinds, [Some(ErrorKind::InvalidParam), None]);
+}
+
+#[test]
+fn decode_pipeline_returns_frames_in_display_order() {
+    let timestamps =
+        decode_blanks(CudaContext::new(0).expect("CUDA should be installed.")).unwrap();
+    assert_eq!(timestamps, (0..64).collect::<Vec<_>>());
+}
+
+#[test]
+fn decode_pipeline_decodes_av1() {
+    let timestamps =
+        decode_av1_blanks(CudaContext::new(0).expect("CUDA should be installed."), 0).unwrap();
+    assert_eq!(timestamps, (0..16).collect::<Vec<_>>());
+}
+
+#[test]
+fn decode_pipeline_falls_back_to_first_av1_operating_point() {
+    // The encoder only writes operating point 0.
+    let timestamps =
+        decode_av1_blanks(CudaContext::new(0).expect("CUDA should be installed."), 7).unwrap();
+    assert_eq!(timestamps, (0..16).collect::<Vec<_>>());
+}
+
+#[test]
+fn decode_pipeline_returns_sei_messages() {
+    let frames =
+        decode_sei_messages(CudaContext::new(0).expect("CUDA should be installed.")).unwrap();
+    assert_eq!(frames.len(), 8);
+    for frame in &frames {
+        let messages = &frame.sei_messages;
+        assert!(messages.contains(&SeiMessage::UserDataUnregistered {
+            uuid: *b"telemetry uuid 0",
+            data: b"frame data".to_vec(),
+        }));
+        assert!(messages.contains(&SeiMessage::MasteringDisplay(MASTERING_DISPLAY)));
+        assert!(
+            messages.contains(&SeiMessage::ContentLightLevel(ContentLightLevel {
+                max_content_light_level: 1000,
+                max_frame_average_light_level: 400,
+            }))
+        );
+    }
+}
+
+#[test]
+fn decode_pipeline_follows_resolution_changes() {
+    let sizes =
+        decode_resolution_changes(CudaContext::new(0).expect("CUDA should be installed.")).unwrap();
+    let expected = [(1280, 720), (1920, 1080), (640, 360)]
+        .into_iter()
+        .flat_map(|size| [size; 16])
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, expected);
+}
+
+#[test]
+fn decode_pipeline_splits_interlaced_frames_into_fields() {
+    let frames =
+        decode_interlaced(CudaContext::new(0).expect("CUDA should be installed.")).unwrap();
+    // The second fields are halfway to the next frame, and the last one is
+    // estimated from the previous frames.
+    let fields = frames
+        .iter()
+        .map(|frame| (frame.pts, frame.second_field))
+        .collect::<Vec<_>>();
+    let expected = (0..8)
+        .flat_map(|i| [(10 * i, false), (10 * i + 5, true)])
+        .collect::<Vec<_>>();
+    assert_eq!(fields, expected);
+    assert!(frames.iter().all(|frame| !frame.progressive
+        && frame.top_field_first
+        && (frame.width, frame.height) == (64, 64)));
+}
+
+#[test]
+fn decoder_letterboxes_thumbnails() {
+    let cuda_ctx = CudaContext::new(0).expect("CUDA should be installed.");
+    let packets = encode_h264_blanks(cuda_ctx.clone(), 1920, 1080, 0..16, &[]).unwrap();
+    let (thumbnails, operating_points) =
+        decode_thumbnails(cuda_ctx, cudaVideoCodec::cudaVideoCodec_H264, &packets).unwrap();
+    assert_eq!(thumbnails.len(), 16);
+    assert!(operating_points.is_empty());
+    // The 16:9 picture is scaled to 320x180, with bars of 70 rows above it.
+    let luma = &thumbnails[0][..320 * 320];
+    assert!(luma[160 * 320..161 * 320].iter().all(|&y| y > 200));
+}
+
+#[test]
+fn parser_falls_back_to_default_av1_operating_point() {
+    let cuda_ctx = CudaContext::new(0).expect("CUDA should be installed.");
+    let packets = encode_av1_blanks(cuda_ctx.clone(), 1920, 1080, 0..16).unwrap();
+    let (thumbnails, operating_points) =
+        decode_thumbnails(cuda_ctx, cudaVideoCodec::cudaVideoCodec_AV1, &packets).unwrap();
+    assert_eq!(thumbnails.len(), 16);
+    // A stream which is not scalable has a single operating point without
+    // layers.
+    assert!(!operating_points.is_empty());
+    assert!(operating_points
+        .iter()
+        .all(|points| points == &[Av1OperatingPoint { idc: 0 }]));
+}